@@ -0,0 +1,190 @@
+use crate::analysis::{Warning, WarningKind};
+use crate::expr::{Expr, ExprIdx, ExprPool};
+use crate::lexer::token::Literal;
+use crate::lox::LoxError;
+use crate::lox_value::LoxValue;
+use crate::stmt::Stmt;
+use crate::symbol::SymbolTable;
+
+pub struct ControlFlow<'a> {
+    // Whether a `break` targets each enclosing loop, innermost last.
+    loops: Vec<bool>,
+    warnings: Vec<Warning>,
+    expr_pool: &'a ExprPool,
+    symbol_table: &'a SymbolTable,
+}
+
+impl<'a> ControlFlow<'a> {
+    pub fn new(expr_pool: &'a ExprPool, symbol_table: &'a SymbolTable) -> Self {
+        ControlFlow {
+            loops: Vec::new(),
+            warnings: Vec::new(),
+            expr_pool,
+            symbol_table,
+        }
+    }
+
+    pub fn check(mut self, statements: &[Stmt]) -> Result<Vec<Warning>, LoxError> {
+        self.statements(statements)?;
+        Ok(self.warnings)
+    }
+
+    // Every visit returns whether control can fall through to the next statement.
+    fn statements(&mut self, statements: &[Stmt]) -> Result<bool, LoxError> {
+        let mut completes = true;
+        let mut last_line = None;
+        for statement in statements {
+            // Statements without a token of their own (e.g. `print("")`) borrow the line before them.
+            last_line = self.stmt_line(statement).or(last_line);
+            if !completes {
+                // Only the first dead statement is reported, the rest of the block follows from it.
+                if let Some(line) = last_line {
+                    self.warnings.push(Warning { kind: WarningKind::UnreachableCode, line, message: "Unreachable code.".into() });
+                }
+                return Ok(false);
+            }
+            completes = self.statement(statement)?;
+        }
+        Ok(completes)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<bool, LoxError> {
+        match stmt {
+            Stmt::Expression { .. } | Stmt::Print { .. } | Stmt::Var { .. } | Stmt::Class { .. } | Stmt::Import { .. } => Ok(true),
+            Stmt::Block { statements } => self.statements(statements),
+            Stmt::If { then_branch, else_branch, .. } => {
+                let then_completes = self.statement(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => Ok(self.statement(else_branch)? || then_completes),
+                    None => Ok(true),
+                }
+            }
+            Stmt::While { condition, body, .. } => {
+                self.loops.push(false);
+                let result = self.statement(body);
+                let breaks = self.loops.pop().unwrap_or(false);
+                result?;
+                // `while (true)` and condition-less `for` loops are only left through `break`.
+                Ok(breaks || !self.is_always_true(*condition))
+            }
+            Stmt::Match { arms, default, .. } => {
+                let mut completes = default.is_none();
+                for body in arms.iter().map(|arm| &arm.body).chain(default.iter()) {
+                    completes |= self.statement(body)?;
+                }
+                Ok(completes)
+            }
+            Stmt::Function { name, return_type, body, .. } => {
+                let enclosing = std::mem::take(&mut self.loops);
+                let result = self.statements(body);
+                self.loops = enclosing;
+                if result? && !matches!(return_type, LoxValue::Void) {
+                    return Err(LoxError::CompilationError(format!(
+                        "[line {}] Function '{}' must return a value of type '{}' on every path.",
+                        name.line,
+                        self.symbol_table.resolve(name.lexeme),
+                        return_type.type_name(self.symbol_table)
+                    )));
+                }
+                Ok(true)
+            }
+            Stmt::Return { .. } | Stmt::Continue { .. } => Ok(false),
+            Stmt::Break { .. } => {
+                if let Some(breaks) = self.loops.last_mut() {
+                    *breaks = true;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn is_always_true(&self, condition: ExprIdx) -> bool {
+        match self.expr_pool.get_expr(condition) {
+            Expr::Literal { value: Literal::True } => true,
+            Expr::Grouping { expression } => self.is_always_true(*expression),
+            _ => false,
+        }
+    }
+
+    fn stmt_line(&self, stmt: &Stmt) -> Option<usize> {
+        match stmt {
+            Stmt::Expression { expression } | Stmt::Print { expression } => self.expr_line(*expression),
+            Stmt::Var { name, .. } | Stmt::Function { name, .. } | Stmt::Class { name, .. } => Some(name.line),
+            Stmt::Block { statements } => statements.iter().find_map(|stmt| self.stmt_line(stmt)),
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => self.expr_line(*condition),
+            Stmt::Match { keyword, .. }
+            | Stmt::Break { keyword }
+            | Stmt::Continue { keyword }
+            | Stmt::Return { keyword, .. }
+            | Stmt::Import { keyword, .. } => Some(keyword.line),
+        }
+    }
+
+    fn expr_line(&self, expr_idx: ExprIdx) -> Option<usize> {
+        match self.expr_pool.get_expr(expr_idx) {
+            Expr::Binary { operator, .. } | Expr::Unary { operator, .. } | Expr::Logical { operator, .. } => Some(operator.line),
+            Expr::Grouping { expression } => self.expr_line(*expression),
+            Expr::Literal { .. } => None,
+            Expr::Cast { target, .. } => Some(target.line),
+            Expr::Variable { name } | Expr::Assign { name, .. } | Expr::Get { name, .. } | Expr::Set { name, .. } => Some(name.line),
+            Expr::Call { paren, .. } => Some(paren.line),
+            Expr::This { keyword } | Expr::Super { keyword, .. } => Some(keyword.line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::scanner::Scanner;
+    use crate::parser::Parser;
+
+    fn check_source(source: &str) -> Result<Vec<Warning>, LoxError> {
+        let mut symbol_table = SymbolTable::new();
+        let tokens = {
+            let mut scanner = Scanner::new(source, &mut symbol_table);
+            scanner.scan_tokens();
+            scanner.tokens
+        };
+        let (statements, expr_pool) = Parser::new(&symbol_table, tokens).parse().unwrap();
+        ControlFlow::new(&expr_pool, &symbol_table).check(&statements)
+    }
+
+    #[test]
+    fn test_missing_return() {
+        let result = check_source("fn f(a: bool) -> int { if (a) { return 1; } }");
+        assert!(matches!(result, Err(LoxError::CompilationError(msg)) if msg.contains("'f' must return a value")));
+    }
+
+    #[test]
+    fn test_both_branches_return() {
+        let result = check_source("fn f(a: bool) -> int { if (a) { return 1; } else { return 2; } }");
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_infinite_loop_needs_no_return() {
+        assert!(check_source("fn f() -> int { while (true) { return 1; } }").is_ok());
+        assert!(check_source("fn f() -> int { while (true) { break; } }").is_err());
+    }
+
+    #[test]
+    fn test_match_with_default_returns() {
+        let source = "fn f(a: int) -> int { match (a) { 0 => return 1; else => return 2; } }";
+        assert!(check_source(source).is_ok());
+        assert!(check_source("fn f(a: int) -> int { match (a) { 0 => return 1; } }").is_err());
+    }
+
+    #[test]
+    fn test_unreachable_after_return() {
+        let warnings = check_source("fn f() -> int {\n return 1;\n let a: int = 2;\n}").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 3);
+    }
+
+    #[test]
+    fn test_unreachable_after_break() {
+        let warnings = check_source("fn f() -> void { while (true) { break; print(\"x\"); } }").unwrap();
+        assert_eq!(warnings.len(), 1);
+    }
+}
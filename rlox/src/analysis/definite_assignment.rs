@@ -0,0 +1,247 @@
+use rustc_hash::FxHashMap;
+
+use crate::expr::{Expr, ExprIdx, ExprPool};
+use crate::lexer::token::{Token, TokenType};
+use crate::lox::LoxError;
+use crate::stmt::Stmt;
+use crate::symbol::{Symbol, SymbolTable};
+
+// Every scope maps the locals declared in it to whether they are definitely assigned.
+type State = Vec<FxHashMap<Symbol, bool>>;
+
+pub struct DefiniteAssignment<'a> {
+    scopes: State,
+    expr_pool: &'a ExprPool,
+    symbol_table: &'a SymbolTable,
+}
+
+impl<'a> DefiniteAssignment<'a> {
+    pub fn new(expr_pool: &'a ExprPool, symbol_table: &'a SymbolTable) -> Self {
+        DefiniteAssignment {
+            scopes: Vec::new(),
+            expr_pool,
+            symbol_table,
+        }
+    }
+
+    pub fn check(mut self, statements: &[Stmt]) -> Result<(), LoxError> {
+        self.statements(statements)
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), LoxError> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        match stmt {
+            Stmt::Expression { expression } | Stmt::Print { expression } => self.expression(*expression),
+            Stmt::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expression(*initializer)?;
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.lexeme, initializer.is_some());
+                }
+                Ok(())
+            }
+            Stmt::Block { statements } => self.scoped(|this| this.statements(statements)),
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.expression(*condition)?;
+                let before = self.scopes.clone();
+                self.scoped(|this| this.statement(then_branch))?;
+                let after_then = std::mem::replace(&mut self.scopes, before);
+                if let Some(else_branch) = else_branch {
+                    self.scoped(|this| this.statement(else_branch))?;
+                }
+                self.merge(&after_then);
+                Ok(())
+            }
+            Stmt::While { condition, body, increment } => {
+                self.expression(*condition)?;
+                // The body might never run, so nothing it assigns counts afterwards.
+                let before = self.scopes.clone();
+                self.scoped(|this| this.statement(body))?;
+                if let Some(increment) = increment {
+                    self.expression(*increment)?;
+                }
+                self.scopes = before;
+                Ok(())
+            }
+            Stmt::Match { subject, arms, default, .. } => {
+                self.expression(*subject)?;
+                let before = self.scopes.clone();
+                let mut paths = Vec::new();
+                for body in arms.iter().map(|arm| &arm.body).chain(default.iter()) {
+                    self.scoped(|this| this.statement(body))?;
+                    paths.push(std::mem::replace(&mut self.scopes, before.clone()));
+                }
+                // Without an `else` arm, no case might match at all.
+                if default.is_some() {
+                    if let Some(first) = paths.pop() {
+                        self.scopes = first;
+                    }
+                }
+                for path in &paths {
+                    self.merge(path);
+                }
+                Ok(())
+            }
+            Stmt::Function { params, body, .. } => {
+                let enclosing = std::mem::take(&mut self.scopes);
+                self.scopes.push(params.iter().map(|param| (param.lexeme, true)).collect());
+                let result = self.statements(body);
+                self.scopes = enclosing;
+                result
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(*value)?;
+                }
+                self.diverge();
+                Ok(())
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {
+                self.diverge();
+                Ok(())
+            }
+            Stmt::Class { .. } | Stmt::Import { .. } => Ok(()),
+        }
+    }
+
+    fn expression(&mut self, expr_idx: ExprIdx) -> Result<(), LoxError> {
+        match self.expr_pool.get_expr(expr_idx) {
+            Expr::Binary { left, right, .. } => {
+                self.expression(*left)?;
+                self.expression(*right)
+            }
+            Expr::Logical { left, right, .. } => {
+                self.expression(*left)?;
+                // The right operand is short-circuited, so its assignments are conditional.
+                let before = self.scopes.clone();
+                self.expression(*right)?;
+                self.scopes = before;
+                Ok(())
+            }
+            Expr::Grouping { expression } | Expr::Cast { expression, .. } => self.expression(*expression),
+            Expr::Unary { right, .. } => self.expression(*right),
+            Expr::Literal { .. } | Expr::This { .. } | Expr::Super { .. } => Ok(()),
+            Expr::Variable { name } => self.read(name),
+            Expr::Assign { name, operator, value } => {
+                if operator.is_some() {
+                    self.read(name)?;
+                }
+                self.expression(*value)?;
+                self.write(name);
+                Ok(())
+            }
+            Expr::Call { callee, arguments, .. } => {
+                self.expression(*callee)?;
+                arguments.iter().try_for_each(|argument| self.expression(*argument))
+            }
+            Expr::Get { object, .. } => self.expression(*object),
+            Expr::Set { object, value, .. } => {
+                self.expression(*value)?;
+                self.expression(*object)
+            }
+        }
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self) -> Result<(), LoxError>) -> Result<(), LoxError> {
+        self.scopes.push(FxHashMap::default());
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+
+    fn read(&self, name: &Token) -> Result<(), LoxError> {
+        if name.token_type != TokenType::Identifier {
+            return Ok(());
+        }
+        match self.scopes.iter().rev().find_map(|scope| scope.get(&name.lexeme)) {
+            Some(false) => Err(LoxError::CompilationError(format!(
+                "[line {}] Variable '{}' is read before it is assigned.",
+                name.line,
+                self.symbol_table.resolve(name.lexeme)
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&mut self, name: &Token) {
+        if let Some(assigned) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&name.lexeme)) {
+            *assigned = true;
+        }
+    }
+
+    // Code after `return`, `break` or `continue` is unreachable, so it can't observe
+    // an unassigned variable. Marking everything assigned lets merges ignore this path.
+    fn diverge(&mut self) {
+        for scope in self.scopes.iter_mut() {
+            scope.values_mut().for_each(|assigned| *assigned = true);
+        }
+    }
+
+    fn merge(&mut self, other: &State) {
+        for (scope, other_scope) in self.scopes.iter_mut().zip(other) {
+            for (name, assigned) in scope.iter_mut() {
+                *assigned &= other_scope.get(name).copied().unwrap_or(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::scanner::Scanner;
+    use crate::parser::Parser;
+
+    fn check_source(source: &str) -> Result<(), LoxError> {
+        let mut symbol_table = SymbolTable::new();
+        let tokens = {
+            let mut scanner = Scanner::new(source, &mut symbol_table);
+            scanner.scan_tokens();
+            scanner.tokens
+        };
+        let (statements, expr_pool) = Parser::new(&symbol_table, tokens).parse().unwrap();
+        DefiniteAssignment::new(&expr_pool, &symbol_table).check(&statements)
+    }
+
+    #[test]
+    fn test_read_before_assignment() {
+        let result = check_source("fn main() -> void { let x: int; let y: int = x; }");
+        assert!(matches!(result, Err(LoxError::CompilationError(msg)) if msg.contains("'x' is read before it is assigned")));
+    }
+
+    #[test]
+    fn test_assignment_before_read() {
+        assert!(check_source("fn main() -> void { let x: int; x = 1; let y: int = x; }").is_ok());
+    }
+
+    #[test]
+    fn test_both_branches_assign() {
+        let source = "fn main() -> void { let x: int; if (true) x = 1; else x = 2; let y: int = x; }";
+        assert!(check_source(source).is_ok());
+    }
+
+    #[test]
+    fn test_one_branch_assigns() {
+        let source = "fn main() -> void { let x: int; if (true) x = 1; let y: int = x; }";
+        assert!(check_source(source).is_err());
+    }
+
+    #[test]
+    fn test_loop_body_assignment_is_conditional() {
+        let source = "fn main() -> void { let x: int; while (false) { x = 1; } let y: int = x; }";
+        assert!(check_source(source).is_err());
+    }
+
+    #[test]
+    fn test_diverging_branch() {
+        let source = "fn f(a: bool) -> int { let x: int; if (a) { x = 1; } else { return 0; } return x; }";
+        assert!(check_source(source).is_ok());
+    }
+}
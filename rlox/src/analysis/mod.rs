@@ -0,0 +1,95 @@
+use std::fmt;
+
+use rustc_hash::FxHashSet;
+
+use crate::lox::LoxError;
+
+pub mod control_flow;
+pub mod definite_assignment;
+pub mod usage;
+
+// Every warning has a name, so it can be switched off with `-Wno-<name>` or an `allow` pragma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    UnreachableCode,
+    UnusedVariable,
+    UnusedParameter,
+    UnusedFunction,
+    Shadowing,
+}
+
+impl WarningKind {
+    pub const ALL: [WarningKind; 5] = [
+        WarningKind::UnreachableCode,
+        WarningKind::UnusedVariable,
+        WarningKind::UnusedParameter,
+        WarningKind::UnusedFunction,
+        WarningKind::Shadowing,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::UnusedVariable => "unused-variable",
+            WarningKind::UnusedParameter => "unused-parameter",
+            WarningKind::UnusedFunction => "unused-function",
+            WarningKind::Shadowing => "shadowing",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, LoxError> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| LoxError::Error(format!("Unknown warning '{}'.", name)))
+    }
+}
+
+// Diagnostics that don't stop compilation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Warning: {} [{}]", self.line, self.message, self.kind.name())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WarningFilter {
+    disabled: FxHashSet<WarningKind>,
+}
+
+impl WarningFilter {
+    pub fn disable(&mut self, kind: WarningKind) {
+        self.disabled.insert(kind);
+    }
+
+    pub fn disable_all(&mut self) {
+        self.disabled.extend(WarningKind::ALL);
+    }
+
+    pub fn is_enabled(&self, kind: WarningKind) -> bool {
+        !self.disabled.contains(&kind)
+    }
+
+    // A `//! allow(name, ...)` comment anywhere in a file silences those warnings for the whole file.
+    pub fn apply_pragmas(&mut self, source: &str) -> Result<(), LoxError> {
+        for (idx, line) in source.lines().enumerate() {
+            let Some(pragma) = line.trim().strip_prefix("//!") else { continue };
+            let Some(names) = pragma.trim().strip_prefix("allow(").and_then(|rest| rest.strip_suffix(')')) else {
+                return Err(LoxError::CompilationError(format!("[line {}] Malformed pragma, expected '//! allow(<warning>, ...)'.", idx + 1)));
+            };
+            for name in names.split(',').map(str::trim) {
+                let kind = WarningKind::from_name(name)
+                    .map_err(|_| LoxError::CompilationError(format!("[line {}] Unknown warning '{}' in pragma.", idx + 1, name)))?;
+                self.disable(kind);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::analysis::{Warning, WarningKind};
+use crate::expr::{Expr, ExprIdx, ExprPool};
+use crate::lexer::token::Token;
+use crate::stmt::Stmt;
+use crate::symbol::{Symbol, SymbolTable};
+
+struct Local {
+    name: Token,
+    parameter: bool,
+    read: bool,
+}
+
+// Reports locals and parameters that are never read, functions that are never
+// referenced and locals that shadow a local of an enclosing scope.
+pub struct Usage<'a> {
+    scopes: Vec<FxHashMap<Symbol, Local>>,
+    functions: Vec<Token>,
+    // Names referenced without resolving to a local, i.e. functions.
+    referenced: FxHashSet<Symbol>,
+    warnings: Vec<Warning>,
+    expr_pool: &'a ExprPool,
+    symbol_table: &'a SymbolTable,
+}
+
+impl<'a> Usage<'a> {
+    pub fn new(expr_pool: &'a ExprPool, symbol_table: &'a SymbolTable) -> Self {
+        Usage {
+            scopes: Vec::new(),
+            functions: Vec::new(),
+            referenced: FxHashSet::default(),
+            warnings: Vec::new(),
+            expr_pool,
+            symbol_table,
+        }
+    }
+
+    pub fn check(mut self, statements: &[Stmt]) -> Vec<Warning> {
+        self.statements(statements);
+        for function in std::mem::take(&mut self.functions) {
+            let name = self.symbol_table.resolve(function.lexeme);
+            if name != "main" && !self.referenced.contains(&function.lexeme) {
+                self.warn(WarningKind::UnusedFunction, &function, format!("Function '{}' is never called.", name));
+            }
+        }
+        self.warnings.sort_by_key(|warning| warning.line);
+        self.warnings
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression } | Stmt::Print { expression } => self.expression(*expression),
+            Stmt::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expression(*initializer);
+                }
+                self.declare(name, false);
+            }
+            Stmt::Block { statements } => self.scoped(|this| this.statements(statements)),
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.expression(*condition);
+                self.scoped(|this| this.statement(then_branch));
+                if let Some(else_branch) = else_branch {
+                    self.scoped(|this| this.statement(else_branch));
+                }
+            }
+            Stmt::While { condition, body, increment } => {
+                self.expression(*condition);
+                self.scoped(|this| this.statement(body));
+                if let Some(increment) = increment {
+                    self.expression(*increment);
+                }
+            }
+            Stmt::Match { subject, arms, default, .. } => {
+                self.expression(*subject);
+                for body in arms.iter().map(|arm| &arm.body).chain(default.iter()) {
+                    self.scoped(|this| this.statement(body));
+                }
+            }
+            Stmt::Function { name, params, body, .. } => {
+                self.functions.push(name.clone());
+                // Shadowing only looks at the locals of the enclosing function body.
+                let enclosing = std::mem::take(&mut self.scopes);
+                self.scoped(|this| {
+                    params.iter().for_each(|param| this.declare(param, true));
+                    this.statements(body);
+                });
+                self.scopes = enclosing;
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(*value);
+                }
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Class { .. } | Stmt::Import { .. } => {}
+        }
+    }
+
+    fn expression(&mut self, expr_idx: ExprIdx) {
+        match self.expr_pool.get_expr(expr_idx) {
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.expression(*left);
+                self.expression(*right);
+            }
+            Expr::Grouping { expression } | Expr::Cast { expression, .. } | Expr::Unary { right: expression, .. } => {
+                self.expression(*expression)
+            }
+            Expr::Literal { .. } | Expr::This { .. } | Expr::Super { .. } => {}
+            Expr::Variable { name } => self.read(name),
+            // Assigning to a local isn't a use of it, not even through a compound operator.
+            Expr::Assign { value, .. } => self.expression(*value),
+            Expr::Call { callee, arguments, .. } => {
+                self.expression(*callee);
+                arguments.iter().for_each(|argument| self.expression(*argument));
+            }
+            Expr::Get { object, .. } => self.expression(*object),
+            Expr::Set { object, value, .. } => {
+                self.expression(*value);
+                self.expression(*object);
+            }
+        }
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(FxHashMap::default());
+        f(self);
+        if let Some(scope) = self.scopes.pop() {
+            let mut unread: Vec<Local> = scope.into_values().filter(|local| !local.read).collect();
+            unread.sort_by_key(|local| local.name.line);
+            for local in unread {
+                let name = self.symbol_table.resolve(local.name.lexeme);
+                // A leading underscore marks a local as intentionally unused.
+                if name.starts_with('_') {
+                    continue;
+                }
+                if local.parameter {
+                    self.warn(WarningKind::UnusedParameter, &local.name, format!("Parameter '{}' is never read.", name));
+                } else {
+                    self.warn(WarningKind::UnusedVariable, &local.name, format!("Variable '{}' is never read.", name));
+                }
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token, parameter: bool) {
+        let (current, enclosing) = match self.scopes.split_last_mut() {
+            Some(split) => split,
+            None => return,
+        };
+        if enclosing.iter().any(|scope| scope.contains_key(&name.lexeme)) {
+            let message = format!("'{}' shadows a variable of an enclosing scope.", self.symbol_table.resolve(name.lexeme));
+            self.warnings.push(Warning { kind: WarningKind::Shadowing, line: name.line, message });
+        }
+        current.insert(name.lexeme, Local { name: name.clone(), parameter, read: false });
+    }
+
+    fn read(&mut self, name: &Token) {
+        match self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&name.lexeme)) {
+            Some(local) => local.read = true,
+            None => {
+                self.referenced.insert(name.lexeme);
+            }
+        }
+    }
+
+    fn warn(&mut self, kind: WarningKind, token: &Token, message: String) {
+        self.warnings.push(Warning { kind, line: token.line, message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::scanner::Scanner;
+    use crate::parser::Parser;
+
+    fn check_source(source: &str) -> Vec<WarningKind> {
+        let mut symbol_table = SymbolTable::new();
+        let tokens = {
+            let mut scanner = Scanner::new(source, &mut symbol_table);
+            scanner.scan_tokens();
+            scanner.tokens
+        };
+        let (statements, expr_pool) = Parser::new(&symbol_table, tokens).parse().unwrap();
+        Usage::new(&expr_pool, &symbol_table).check(&statements).into_iter().map(|warning| warning.kind).collect()
+    }
+
+    #[test]
+    fn test_unused_variable() {
+        assert_eq!(check_source("fn main() -> void { let a = 1; a = 2; }"), vec![WarningKind::UnusedVariable]);
+        assert!(check_source("fn main() -> void { let a = 1; let b = a; let _c = b; }").is_empty());
+    }
+
+    #[test]
+    fn test_unused_parameter() {
+        let source = "fn f(a: int, b: int) -> int { return a; } fn main() -> void { let c = f(1, 2); print(\"\"); }";
+        assert_eq!(check_source(source), vec![WarningKind::UnusedParameter, WarningKind::UnusedVariable]);
+    }
+
+    #[test]
+    fn test_unused_function() {
+        assert_eq!(check_source("fn f() -> void { } fn main() -> void { }"), vec![WarningKind::UnusedFunction]);
+        assert!(check_source("fn main() -> void { g(); } fn g() -> void { }").is_empty());
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let source = "fn main() -> void { let a = 1; { let a = 2; let b = a; let _e = b; } let c = a; let _d = c; }";
+        assert_eq!(check_source(source), vec![WarningKind::Shadowing]);
+    }
+}
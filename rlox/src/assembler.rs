@@ -0,0 +1,320 @@
+use std::io::{self, Write};
+
+use rustc_hash::FxHashMap;
+
+use crate::il::{Constant, Il, Instr, Size, SourceLocation};
+use crate::lox::LoxError;
+
+// Memory reserved for the program, the same values the `.prep` section of the text IL asks for.
+pub const STACK_SIZE: u32 = 1032;
+pub const HEAP_SIZE: u32 = 1024;
+
+// The bytecode is rlox-jasm's own, made for the embedded VM. It isn't JASM's `.jef` format and
+// CSR can't load it, files for CSR are assembled by JASM. Writing `.jef` here would need its
+// layout, which rlox-jasm doesn't know.
+pub(crate) const MAGIC: &[u8; 3] = b"RJB";
+pub(crate) const VERSION: u8 = 1;
+// Magic, version, entry, stack size, heap size, code length.
+pub(crate) const HEADER_SIZE: usize = 20;
+
+const ENTRY_LABEL: &str = "__jasm_IL_entry_main__";
+const END_LABEL: &str = "__jasm_IL_end__";
+
+// One opcode per instruction form. Operands follow the opcode: sizes, registers, modes and
+// operators are a single byte each, numbers and addresses are little endian.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Stc = 0x01,
+    Arith = 0x02,
+    ArithReg = 0x03,
+    Cmp = 0x04,
+    Cnv = 0x05,
+    Inc = 0x06,
+    Dcr = 0x07,
+    MovTop = 0x08,
+    MovReg = 0x09,
+    MovImm = 0x0a,
+    Pop = 0x0b,
+    Rda = 0x0c,
+    RdaReg = 0x0d,
+    Ldc = 0x0e,
+    Mcp = 0x0f,
+    Alc = 0x10,
+    Del = 0x11,
+    Cnd = 0x12,
+    Jmp = 0x13,
+    JmpReg = 0x14,
+    Cal = 0x15,
+    Ret = 0x16,
+    Raw = 0x17,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 23] = [
+        Opcode::Stc, Opcode::Arith, Opcode::ArithReg, Opcode::Cmp, Opcode::Cnv, Opcode::Inc, Opcode::Dcr,
+        Opcode::MovTop, Opcode::MovReg, Opcode::MovImm, Opcode::Pop, Opcode::Rda, Opcode::RdaReg, Opcode::Ldc,
+        Opcode::Mcp, Opcode::Alc, Opcode::Del, Opcode::Cnd, Opcode::Jmp, Opcode::JmpReg, Opcode::Cal, Opcode::Ret,
+        Opcode::Raw,
+    ];
+}
+
+// `ArithReg` operand for flag registers, which take no size suffix.
+pub(crate) const NO_SIZE: u8 = 0xff;
+
+// Maps code addresses back to the Lox source. Each entry covers the code up to the next one.
+#[derive(Debug, Default, PartialEq)]
+pub struct DebugMap {
+    pub entries: Vec<(u32, SourceLocation)>,
+}
+
+impl DebugMap {
+    pub fn lookup(&self, address: u32) -> Option<SourceLocation> {
+        let idx = self.entries.partition_point(|(start, _)| *start <= address);
+        idx.checked_sub(1).map(|idx| self.entries[idx].1)
+    }
+
+    fn record(&mut self, address: u32, location: SourceLocation) {
+        match self.entries.last_mut() {
+            Some((_, last)) if *last == location => {}
+            // Labels and comments take no space, the instruction after them wins.
+            Some((start, last)) if *start == address => *last = location,
+            _ => self.entries.push((address, location)),
+        }
+    }
+
+    // One `<address> <line>:<column>` entry per line.
+    pub fn write_to(&self, source: &str, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "rlox-jasm debug map")?;
+        writeln!(out, "source {}", source)?;
+        for (address, location) in &self.entries {
+            writeln!(out, "{:#010x} {}:{}", address, location.line, location.column)?;
+        }
+        Ok(())
+    }
+}
+
+// Assembles a program for the embedded VM: the header, then the code, which starts with the
+// same `cal main` / `jmp <end>` entry the text IL uses.
+//
+//   "RJB" version:u8 entry:u32 stack:u32 heap:u32 code_len:u32 code...
+pub fn assemble(il: &Il, out: &mut impl Write) -> Result<DebugMap, LoxError> {
+    assemble_entry(il, "main", STACK_SIZE, HEAP_SIZE, out)
+}
+
+// Same as `assemble`, but the entry calls `entry` and the header asks for the given memory.
+pub fn assemble_entry(il: &Il, entry: &str, stack_size: u32, heap_size: u32, out: &mut impl Write) -> Result<DebugMap, LoxError> {
+    let mut assembler = Assembler::default();
+    let mut debug_map = DebugMap::default();
+    for instr in [Instr::Label(ENTRY_LABEL.into()), Instr::Cal(entry.into()), Instr::Jmp(END_LABEL.into())] {
+        assembler.instr(&instr)?;
+    }
+    for line in &il.lines {
+        if let Some(location) = line.location {
+            debug_map.record(address(assembler.code.len())?, location);
+        }
+        assembler.instr(&line.instr)?;
+    }
+    assembler.instr(&Instr::Label(END_LABEL.into()))?;
+    let code = assembler.finish()?;
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    // The entry code is emitted first, so execution starts at the beginning of the code.
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&stack_size.to_le_bytes())?;
+    out.write_all(&heap_size.to_le_bytes())?;
+    out.write_all(&address(code.len())?.to_le_bytes())?;
+    out.write_all(&code)?;
+    Ok(debug_map)
+}
+
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+    labels: FxHashMap<String, u32>,
+    // Label references waiting for their address, by the offset of the placeholder.
+    fixups: Vec<(usize, String)>,
+}
+
+impl Assembler {
+    fn instr(&mut self, instr: &Instr) -> Result<(), LoxError> {
+        match instr {
+            Instr::Stc(value) => {
+                self.op(Opcode::Stc, &[value.size() as u8]);
+                match value {
+                    Constant::Byte(byte) => self.code.push(*byte),
+                    Constant::Int(int) => self.code.extend(int.to_le_bytes()),
+                    Constant::Float(float) => self.code.extend((f64::from(float) as f32).to_le_bytes()),
+                }
+            }
+            Instr::Arith(op, size) => self.op(Opcode::Arith, &[*op as u8, *size as u8]),
+            Instr::ArithReg(op, size, a, b) => {
+                self.op(Opcode::ArithReg, &[*op as u8, size.map_or(NO_SIZE, |size| size as u8), *a as u8, *b as u8])
+            }
+            Instr::Cmp(size, mode) => self.op(Opcode::Cmp, &[*size as u8, *mode as u8]),
+            Instr::Cnv(from, to) => self.op(Opcode::Cnv, &[*from as u8, *to as u8]),
+            Instr::Inc(size, reg, amount) | Instr::Dcr(size, reg, amount) => {
+                let opcode = if matches!(instr, Instr::Inc(..)) { Opcode::Inc } else { Opcode::Dcr };
+                self.op(opcode, &[*size as u8, *reg as u8]);
+                self.sized(*size, *amount)?;
+            }
+            Instr::MovTop(reg) => self.op(Opcode::MovTop, &[*reg as u8]),
+            Instr::MovReg(from, to) => self.op(Opcode::MovReg, &[*from as u8, *to as u8]),
+            Instr::MovImm(value, reg) => {
+                self.op(Opcode::MovImm, &[*reg as u8]);
+                self.code.extend(address(*value)?.to_le_bytes());
+            }
+            Instr::MovLabel(label, reg) => {
+                self.op(Opcode::MovImm, &[*reg as u8]);
+                self.label_ref(label);
+            }
+            Instr::Pop(size) => self.op(Opcode::Pop, &[*size as u8]),
+            Instr::Rda(size) => self.op(Opcode::Rda, &[*size as u8]),
+            Instr::RdaReg(reg) => self.op(Opcode::RdaReg, &[*reg as u8]),
+            Instr::Ldc(size) => self.op(Opcode::Ldc, &[*size as u8]),
+            Instr::Mcp(from, to) => self.op(Opcode::Mcp, &[*from as u8, *to as u8]),
+            Instr::Alc => self.op(Opcode::Alc, &[]),
+            Instr::Del => self.op(Opcode::Del, &[]),
+            Instr::Cnd(label) => {
+                self.op(Opcode::Cnd, &[]);
+                self.label_ref(label);
+            }
+            Instr::Jmp(label) => {
+                self.op(Opcode::Jmp, &[]);
+                self.label_ref(label);
+            }
+            Instr::JmpReg(reg) => self.op(Opcode::JmpReg, &[*reg as u8]),
+            Instr::Cal(name) => {
+                self.op(Opcode::Cal, &[]);
+                self.label_ref(name);
+            }
+            Instr::CalAddr(addr) => {
+                self.op(Opcode::Cal, &[]);
+                self.code.extend(addr.to_le_bytes());
+            }
+            Instr::Ret => self.op(Opcode::Ret, &[]),
+            Instr::Raw(text) => {
+                self.op(Opcode::Raw, &[]);
+                self.code.extend(address(text.len())?.to_le_bytes());
+                self.code.extend(text.as_bytes());
+            }
+            Instr::Label(label) => {
+                let addr = address(self.code.len())?;
+                if self.labels.insert(label.clone(), addr).is_some() {
+                    return Err(LoxError::CompilationError(format!("Label '{}' is defined more than once.", label)));
+                }
+            }
+            Instr::Comment(_) => {}
+        }
+        Ok(())
+    }
+
+    fn op(&mut self, opcode: Opcode, operands: &[u8]) {
+        self.code.push(opcode as u8);
+        self.code.extend(operands);
+    }
+
+    fn sized(&mut self, size: Size, value: usize) -> Result<(), LoxError> {
+        match size {
+            Size::Byte => {
+                let byte = u8::try_from(value)
+                    .map_err(|_| LoxError::CompilationError(format!("Value {} doesn't fit into a byte.", value)))?;
+                self.code.push(byte);
+            }
+            Size::Int | Size::Float => self.code.extend(address(value)?.to_le_bytes()),
+        }
+        Ok(())
+    }
+
+    fn label_ref(&mut self, label: &str) {
+        self.fixups.push((self.code.len(), label.to_string()));
+        self.code.extend([0; 4]);
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, LoxError> {
+        for (offset, label) in std::mem::take(&mut self.fixups) {
+            let addr = self.labels
+                .get(&label)
+                .ok_or_else(|| LoxError::CompilationError(format!("Undefined label '{}'.", label)))?;
+            self.code[offset..offset + 4].copy_from_slice(&addr.to_le_bytes());
+        }
+        Ok(self.code)
+    }
+}
+
+fn address(value: usize) -> Result<u32, LoxError> {
+    u32::try_from(value).map_err(|_| LoxError::CompilationError(format!("Value {} doesn't fit into 32 bits.", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::il::Reg;
+    use crate::interpreter::JUMP_TABLE_STRIDE;
+
+    fn code(instrs: Vec<Instr>) -> Vec<u8> {
+        let mut assembler = Assembler::default();
+        instrs.iter().for_each(|instr| assembler.instr(instr).unwrap());
+        assembler.finish().unwrap()
+    }
+
+    #[test]
+    fn test_jump_matches_jump_table_stride() {
+        let jump = code(vec![Instr::Label("a".into()), Instr::Jmp("a".into())]);
+        assert_eq!(jump.len(), JUMP_TABLE_STRIDE);
+    }
+
+    #[test]
+    fn test_forward_labels_are_resolved() {
+        let instrs = vec![
+            Instr::Cnd("end".into()),
+            Instr::comment("skipped"),
+            Instr::Stc(Constant::Int(-1)),
+            Instr::Label("end".into()),
+            Instr::MovLabel("end".into(), Reg::Ebx),
+        ];
+        assert_eq!(code(instrs), vec![
+            Opcode::Cnd as u8, 11, 0, 0, 0,
+            Opcode::Stc as u8, Size::Int as u8, 0xff, 0xff, 0xff, 0xff,
+            Opcode::MovImm as u8, Reg::Ebx as u8, 11, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn test_debug_map() {
+        let at = |line, column| Some(SourceLocation { line, column });
+        let mut il = Il::new();
+        il.set_location(at(1, 4));
+        il.push(0, Instr::Label("main".into()));
+        il.set_location(at(2, 5));
+        il.push(1, Instr::comment("variable a"));
+        il.push(1, Instr::Stc(Constant::Int(1)));
+        il.set_location(at(3, 9));
+        il.push(1, Instr::Stc(Constant::Byte(1)));
+        il.push(1, Instr::Ret);
+
+        let map = assemble(&il, &mut Vec::new()).unwrap();
+        assert_eq!(map.entries.iter().map(|(address, _)| *address).collect::<Vec<_>>(), vec![10, 16]);
+        assert_eq!(map.lookup(9), None);
+        assert_eq!(map.lookup(12), at(2, 5));
+        assert_eq!(map.lookup(19), at(3, 9));
+    }
+
+    #[test]
+    fn test_program_layout() {
+        let mut il = Il::new();
+        il.push(0, Instr::Label("main".into()));
+        il.push(1, Instr::Ret);
+        let mut out = Vec::new();
+        assemble(&il, &mut out).unwrap();
+        assert_eq!(&out[..4], b"RJB\x01");
+        assert_eq!(out[12..16], HEAP_SIZE.to_le_bytes());
+        // cal main, jmp <end>, ret
+        assert_eq!(out[20..], [Opcode::Cal as u8, 10, 0, 0, 0, Opcode::Jmp as u8, 11, 0, 0, 0, Opcode::Ret as u8]);
+
+        let mut undefined = Il::new();
+        undefined.push(0, Instr::Jmp("nowhere".into()));
+        assert!(assemble(&undefined, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+use crate::analysis::WarningKind;
+use crate::lox::{CompileOptions, LoxError};
+
+// Largest stack or heap a program can ask for.
+const MAX_MEMORY: u32 = 1 << 28;
+
+// Flags taking a value, which can also be written as `--flag=value`.
+const VALUE_FLAGS: &[&str] = &["--opt-level", "--out-dir", "--stack-size", "--heap-size", "--jasm-path", "--csr-path"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(Vec<String>),
+    Build(Vec<String>),
+    Jasm(Vec<String>),
+    Check(Vec<String>),
+    Fmt { files: Vec<String>, check: bool },
+    Repl,
+    Interpret(Vec<String>),
+    Difftest(Vec<String>),
+    Help,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub command: Command,
+    pub options: CompileOptions,
+    // Runs on CSR instead of the embedded VM (`--csr`).
+    pub csr: bool,
+}
+
+// Flags can come before or after the command. Errors are usage errors, meant to be printed
+// together with a pointer to `help`.
+pub fn parse(args: &[String]) -> Result<Cli, String> {
+    let mut options = CompileOptions::default();
+    let (mut csr, mut check) = (false, false);
+    let mut positional: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if VALUE_FLAGS.contains(&flag) => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let value = || inline.or_else(|| args.next().cloned()).ok_or_else(|| format!("'{}' expects a value.", flag));
+        match flag {
+            "-h" | "--help" => return Ok(Cli { command: Command::Help, options, csr }),
+            "-O" | "-O1" => options.optimize = true,
+            "-O0" => options.optimize = false,
+            "--opt-level" => {
+                options.optimize = match value()?.as_str() {
+                    "0" => false,
+                    "1" => true,
+                    level => return Err(format!("Unknown optimization level '{}', expected 0 or 1.", level)),
+                }
+            }
+            "--annotate" => options.annotate = true,
+            "-g" | "--emit-debug-info" => options.debug_info = true,
+            "-o" => options.out_file = Some(PathBuf::from(value()?)),
+            "--out-dir" => options.out_dir = Some(PathBuf::from(value()?)),
+            "--stack-size" => options.stack_size = memory_size(flag, &value()?)?,
+            "--heap-size" => options.heap_size = memory_size(flag, &value()?)?,
+            "--jasm-path" => options.jasm_path = Some(PathBuf::from(value()?)),
+            "--csr-path" => options.csr_path = Some(PathBuf::from(value()?)),
+            "--csr" => csr = true,
+            "--check" => check = true,
+            "-w" => options.warnings.disable_all(),
+            _ if flag.starts_with("-Wno-") => {
+                let kind = WarningKind::from_name(&flag["-Wno-".len()..]).map_err(|err| match err {
+                    LoxError::Error(message) => message,
+                    err => err.to_string(),
+                })?;
+                options.warnings.disable(kind);
+            }
+            _ if flag.starts_with('-') && flag.len() > 1 => return Err(format!("Unknown flag '{}'.", arg)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let Some((name, files)) = positional.split_first() else {
+        return Err("Missing command.".into());
+    };
+    let files = files.to_vec();
+    let with_files = |files: Vec<String>| match files.is_empty() {
+        true => Err(format!("'{}' expects at least one file.", name)),
+        false => Ok(files),
+    };
+    let command = match name.as_str() {
+        "run" => Command::Run(with_files(files)?),
+        "build" => Command::Build(with_files(files)?),
+        "jasm" => Command::Jasm(with_files(files)?),
+        "check" => Command::Check(with_files(files)?),
+        "fmt" => Command::Fmt { files: with_files(files)?, check },
+        "repl" if files.is_empty() => Command::Repl,
+        "repl" => return Err("'repl' takes no files.".into()),
+        "interpret" => Command::Interpret(with_files(files)?),
+        "difftest" => Command::Difftest(with_files(files)?),
+        "help" => Command::Help,
+        _ => return Err(format!("Unknown command '{}'.", name)),
+    };
+    if check && !matches!(command, Command::Fmt { .. }) {
+        return Err("'--check' only applies to 'fmt'.".into());
+    }
+    if options.debug_info && !matches!(command, Command::Build(_)) {
+        return Err("'-g' only applies to 'build'.".into());
+    }
+    if options.out_file.is_some() && matches!(&command, Command::Build(files) | Command::Jasm(files) if files.len() > 1) {
+        return Err("'-o' takes a single source file, use '--out-dir' for more.".into());
+    }
+    Ok(Cli { command, options, csr })
+}
+
+fn memory_size(flag: &str, value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(size) if (1..=MAX_MEMORY).contains(&size) => Ok(size),
+        _ => Err(format!("'{}' expects a size between 1 and {} bytes, got '{}'.", flag, MAX_MEMORY, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Cli, String> {
+        parse(&args.split_whitespace().map(String::from).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_commands_and_flags() {
+        let cli = parse_str("-O build a.lox --stack-size 4096 --heap-size=2048 -g -Wno-shadowing --out-dir=out b.lox").unwrap();
+        assert_eq!(cli.command, Command::Build(vec!["a.lox".into(), "b.lox".into()]));
+        let options = cli.options;
+        assert!(options.optimize && options.debug_info);
+        assert_eq!((options.stack_size, options.heap_size), (4096, 2048));
+        assert!(!options.warnings.is_enabled(WarningKind::Shadowing));
+        assert_eq!(options.out_dir, Some(PathBuf::from("out")));
+
+        assert_eq!(parse_str("fmt --check a.lox").unwrap().command, Command::Fmt { files: vec!["a.lox".into()], check: true });
+        assert_eq!(parse_str("repl --opt-level 0").unwrap().command, Command::Repl);
+        assert!(parse_str("run --csr --csr-path /opt/csr a.lox").unwrap().csr);
+        assert_eq!(parse_str("run a.lox --help").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        for (args, message) in [
+            ("", "Missing command."),
+            ("compile a.lox", "Unknown command 'compile'."),
+            ("run", "'run' expects at least one file."),
+            ("repl a.lox", "'repl' takes no files."),
+            ("check a.lox --check", "'--check' only applies to 'fmt'."),
+            ("run a.lox -g", "'-g' only applies to 'build'."),
+            ("jasm a.lox --emit-debug-info", "'-g' only applies to 'build'."),
+            ("run a.lox --fast", "Unknown flag '--fast'."),
+            ("run a.lox -o", "'-o' expects a value."),
+            ("build a.lox b.lox -o a.jef", "'-o' takes a single source file, use '--out-dir' for more."),
+            ("run a.lox --opt-level=3", "Unknown optimization level '3', expected 0 or 1."),
+            ("run a.lox --stack-size 0", "'--stack-size' expects a size between 1 and 268435456 bytes, got '0'."),
+            ("run a.lox -Wno-everything", "Unknown warning 'everything'."),
+        ] {
+            assert_eq!(parse_str(args).unwrap_err(), message, "{}", args);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::lox::{self, CompileOptions, LoxError};
+
+// What a program printed and the error it stopped with, if any.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    pub stdout: String,
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    fn new(stdout: Vec<u8>, result: Result<(), LoxError>) -> Self {
+        Outcome {
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            error: result.err().map(|err| match err {
+                LoxError::RuntimeError(message) => message,
+                err => err.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "stdout {:?}, error {:?}", self.stdout, self.error)
+    }
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    pub name: String,
+    pub expected: Outcome,
+    pub interpreted: Outcome,
+    pub compiled: Outcome,
+    // Annotated IL of the program, or the error that kept it from compiling.
+    pub il: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "FAIL {}", self.name)?;
+        writeln!(f, "    expected:    {}", self.expected)?;
+        writeln!(f, "    interpreted: {}", self.interpreted)?;
+        writeln!(f, "    compiled:    {}", self.compiled)?;
+        write!(f, "    IL:")?;
+        for line in self.il.trim_start().lines() {
+            write!(f, "\n        {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub passed: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+// Reads `// expect: <line>` and `// expect runtime error: <message>` comments, the same way
+// the Crafting Interpreters test suite does.
+pub fn expectations(source: &str) -> Outcome {
+    let mut expected = Outcome::default();
+    for (idx, line) in source.lines().enumerate() {
+        if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            expected.error = Some(format!("[line {}] {}", idx + 1, message));
+        } else if let Some((_, output)) = line.split_once("// expect: ") {
+            expected.stdout.push_str(output);
+            expected.stdout.push('\n');
+        }
+    }
+    expected
+}
+
+// Runs a program on the tree-walking interpreter and on the VM, both have to match its expectations.
+pub fn check_source(name: &str, source: &str) -> Result<Option<Mismatch>, LoxError> {
+    let mut options = CompileOptions::default().for_vm();
+    options.warnings.disable_all();
+
+    let expected = expectations(source);
+    let mut stdout = Vec::new();
+    let result = lox::interpret_typed_source(source, &mut stdout);
+    let interpreted = Outcome::new(stdout, result);
+    let mut stdout = Vec::new();
+    let result = lox::run_source(source, &options, &mut stdout);
+    let compiled = Outcome::new(stdout, result);
+
+    if interpreted == expected && compiled == expected {
+        return Ok(None);
+    }
+
+    let il = match lox::compile_il(source, &options) {
+        Ok(il) => {
+            let mut listing = Vec::new();
+            il.write_annotated(&mut listing, source)?;
+            String::from_utf8_lossy(&listing).into_owned()
+        }
+        Err(err) => err.to_string(),
+    };
+    Ok(Some(Mismatch { name: name.to_string(), expected, interpreted, compiled, il }))
+}
+
+// Checks every `.lox` file directly inside `dir`, in name order.
+pub fn run_dir(dir: &Path) -> Result<Report, LoxError> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "lox"));
+    paths.sort();
+
+    let mut report = Report::default();
+    for path in paths {
+        let source = fs::read_to_string(&path)?;
+        match check_source(&path.display().to_string(), &source)? {
+            Some(mismatch) => report.mismatches.push(mismatch),
+            None => report.passed += 1,
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expectations() {
+        let source = "print(\"a\"); // expect: a\n// expect: b c\nlet x = 1 / 0; // expect runtime error: Division by zero.";
+        let expected = expectations(source);
+        assert_eq!(expected.stdout, "a\nb c\n");
+        assert_eq!(expected.error.as_deref(), Some("[line 3] Division by zero."));
+    }
+
+    #[test]
+    fn test_mismatch_carries_il() {
+        let source = "fn main() -> void {\n    let s = \"actual\";\n    print(s); // expect: wrong\n}";
+        let mismatch = check_source("wrong.lox", source).unwrap().expect("the expectation is wrong");
+        assert_eq!(mismatch.interpreted.stdout, "actual\n");
+        assert_eq!(mismatch.compiled.stdout, "actual\n");
+        assert!(mismatch.il.contains("#   3 | print(s); // expect: wrong#"));
+    }
+
+    #[test]
+    fn test_suite() {
+        let report = run_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox")).unwrap();
+        let failures: Vec<String> = report.mismatches.iter().map(Mismatch::to_string).collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+        assert!(report.passed > 0);
+    }
+}
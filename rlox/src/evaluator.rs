@@ -0,0 +1,506 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::environment::{Environment, EnvironmentError};
+use crate::expr::{Expr, ExprIdx, ExprPool};
+use crate::globals::define_globals;
+use crate::lexer::token::{Literal, Token, TokenType};
+use crate::lox::LoxError;
+use crate::lox_callable::callable::Callable;
+use crate::lox_callable::lox_class::LoxClass;
+use crate::lox_callable::lox_function::LoxFunction;
+use crate::lox_callable::lox_instance::LoxInstance;
+use crate::lox_value::{LoxCallable, LoxValue, LoxValueError};
+use crate::stmt::Stmt;
+use crate::symbol::SymbolTable;
+
+// Every Lox call nests several host frames, so this stays well below what the host stack allows.
+const MAX_CALL_DEPTH: usize = 256;
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    IncorrectOperand(Token, LoxValueError),
+    DivideByZero(Token, LoxValueError),
+    InterpreterPanic(Token, String),
+    InstanceError(Token, String),
+    UndefinedVariable(Token, EnvironmentError),
+    AssignVariableError(Token, EnvironmentError),
+    InputError(String),
+    CustomError(String),
+    Return(LoxValue),
+    Break,
+    Continue,
+}
+
+impl RuntimeError {
+    pub fn get_info(self) -> (Token, String) {
+        match self {
+            RuntimeError::IncorrectOperand(token, lox_value_err)
+            | RuntimeError::DivideByZero(token, lox_value_err) => {
+                (token, lox_value_err.get_string())
+            }
+            RuntimeError::UndefinedVariable(token, environment_err)
+            | RuntimeError::AssignVariableError(token, environment_err) => {
+                (token, environment_err.get_string())
+            }
+            RuntimeError::InterpreterPanic(token, err_str)
+            | RuntimeError::InstanceError(token, err_str) => (token, err_str),
+            _ => panic!("Should not reach here!"),
+        }
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        match error {
+            RuntimeError::InputError(message) | RuntimeError::CustomError(message) => LoxError::RuntimeError(message),
+            // The resolver rejects these outside of functions and loops, which catch them.
+            RuntimeError::Return(_) | RuntimeError::Break | RuntimeError::Continue => unreachable!(),
+            error => {
+                let (token, message) = error.get_info();
+                LoxError::RuntimeError(format!("[line {}] {}", token.line, message))
+            }
+        }
+    }
+}
+
+// Tree-walking interpreter for scripts, type annotations are parsed but never checked.
+pub struct Evaluator<'a> {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    locals: FxHashMap<ExprIdx, usize>,
+    expr_pool: &'a ExprPool,
+    pub symbol_table: &'a mut SymbolTable,
+    out: &'a mut dyn Write,
+    call_depth: usize,
+    // Untyped Lox only has `num`, typed programs also have `int` like on the VM.
+    untyped: bool,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(
+        expr_pool: &'a ExprPool,
+        symbol_table: &'a mut SymbolTable,
+        locals: FxHashMap<ExprIdx, usize>,
+        out: &'a mut dyn Write,
+    ) -> Self {
+        let globals = Environment::new();
+        define_globals(&globals, symbol_table);
+
+        Self {
+            environment: Rc::clone(&globals),
+            globals,
+            locals,
+            expr_pool,
+            symbol_table,
+            out,
+            call_depth: 0,
+            untyped: false,
+        }
+    }
+
+    pub fn untyped(mut self) -> Self {
+        self.untyped = true;
+        self
+    }
+
+    pub fn get_globals(&self) -> Rc<RefCell<Environment>> {
+        Rc::clone(&self.globals)
+    }
+
+    // Scripts made only of declarations start at `main`, the same as compiled programs.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        for statement in statements {
+            self.execute(statement)?;
+        }
+
+        if statements.iter().all(|stmt| matches!(stmt, Stmt::Function { .. } | Stmt::Class { .. })) {
+            let main = self.symbol_table.intern("main");
+            let main = self.globals.borrow().get(main, self.symbol_table);
+            if let Ok(LoxValue::Callable(main)) = main {
+                main.call(self, Vec::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute_block(&mut self, statements: &[Stmt], environment: Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = statements.iter().try_for_each(|statement| self.execute(statement));
+        self.environment = previous;
+        result
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.evaluate(*expression)?;
+            }
+            Stmt::Print { expression } => {
+                let value = self.evaluate(*expression)?;
+                writeln!(self.out, "{}", value).map_err(|err| RuntimeError::CustomError(err.to_string()))?;
+            }
+            Stmt::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(*initializer)?,
+                    // Annotated declarations start out zeroed, like their compiled counterparts.
+                    None => match &name.literal {
+                        Literal::Class(_) => LoxValue::Void,
+                        literal => literal.into(),
+                    },
+                };
+                self.environment.borrow_mut().define(name.lexeme, value);
+            }
+            Stmt::Block { statements } => {
+                let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+                self.execute_block(statements, environment)?;
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                if self.is_truthy(*condition)? {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            Stmt::While { condition, body, increment } => {
+                while self.is_truthy(*condition)? {
+                    match self.execute(body) {
+                        Err(RuntimeError::Break) => break,
+                        Ok(()) | Err(RuntimeError::Continue) => (),
+                        Err(err) => return Err(err),
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(*increment)?;
+                    }
+                }
+            }
+            Stmt::Match { subject, arms, default, .. } => {
+                let subject = self.evaluate(*subject)?;
+                let arm = arms
+                    .iter()
+                    .find(|arm| arm.patterns.iter().any(|pattern| LoxValue::from(pattern) == subject))
+                    .map(|arm| &arm.body)
+                    .or(default.as_ref());
+                if let Some(body) = arm {
+                    self.execute(body)?;
+                }
+            }
+            Stmt::Break { .. } => return Err(RuntimeError::Break),
+            Stmt::Continue { .. } => return Err(RuntimeError::Continue),
+            Stmt::Function { name, params, body, .. } => {
+                let function = LoxFunction::new(name.clone(), params.clone(), body.clone(), Rc::clone(&self.environment), false);
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme, LoxValue::Callable(LoxCallable::Function(Rc::new(function))));
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(*value)?,
+                    None => LoxValue::Void,
+                };
+                return Err(RuntimeError::Return(value));
+            }
+            Stmt::Class { name, superclass, methods } => self.class_stmt(name, *superclass, methods)?,
+            Stmt::Import { keyword, .. } => {
+                return Err(RuntimeError::InterpreterPanic(keyword.clone(), "Imports are only supported by the compiler.".into()))
+            }
+        }
+        Ok(())
+    }
+
+    fn class_stmt(&mut self, name: &Token, superclass: Option<ExprIdx>, methods: &[Stmt]) -> Result<(), RuntimeError> {
+        let superclass = match superclass {
+            Some(superclass_idx) => match self.evaluate(superclass_idx)? {
+                LoxValue::Callable(LoxCallable::Class(class)) => Some(class),
+                _ => return Err(RuntimeError::InstanceError(name.clone(), "Superclass must be a class.".into())),
+            },
+            None => None,
+        };
+
+        self.environment.borrow_mut().define(name.lexeme, LoxValue::Void);
+        let enclosing = Rc::clone(&self.environment);
+        if let Some(superclass) = &superclass {
+            self.environment = Environment::with_enclosing(Rc::clone(&enclosing));
+            let super_symbol = self.symbol_table.intern("super");
+            self.environment
+                .borrow_mut()
+                .define(super_symbol, LoxValue::Callable(LoxCallable::Class(Rc::clone(superclass))));
+        }
+
+        let init = self.symbol_table.intern("init");
+        let mut class_methods = FxHashMap::default();
+        for method in methods {
+            if let Stmt::Function { name, params, body, .. } = method {
+                let function = LoxFunction::new(name.clone(), params.clone(), body.clone(), Rc::clone(&self.environment), name.lexeme == init);
+                class_methods.insert(name.lexeme, LoxCallable::Function(Rc::new(function)));
+            }
+        }
+
+        let class = LoxClass::new(self.symbol_table.resolve(name.lexeme).to_string(), superclass, class_methods);
+        self.environment = enclosing;
+        self.environment
+            .borrow_mut()
+            .assign(name.lexeme, LoxValue::Callable(LoxCallable::Class(Rc::new(class))), self.symbol_table)
+            .map_err(|err| RuntimeError::AssignVariableError(name.clone(), err))?;
+        Ok(())
+    }
+
+    fn is_truthy(&mut self, expr_idx: ExprIdx) -> Result<bool, RuntimeError> {
+        Ok(self.evaluate(expr_idx)?.is_truthy() == LoxValue::Boolean(true))
+    }
+
+    // Each arm gets its own method, debug builds would otherwise give every recursion the frame of the largest.
+    fn evaluate(&mut self, expr_idx: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        let expr_pool = self.expr_pool;
+        match expr_pool.get_expr(expr_idx) {
+            Expr::Literal { value } => Ok(value.into()),
+            Expr::Grouping { expression } => self.evaluate(*expression),
+            Expr::Unary { operator, right } => self.unary_expr(operator, *right),
+            Expr::Cast { target, expression } => self.cast_expr(target, *expression),
+            Expr::Binary { left, operator, right } => self.binary_expr(*left, operator, *right),
+            Expr::Logical { left, operator, right } => self.logical_expr(*left, operator, *right),
+            Expr::Variable { name } => self.look_up_variable(name, expr_idx),
+            Expr::Assign { name, operator, value } => self.assign_expr(expr_idx, name, operator.as_ref(), *value),
+            Expr::Call { callee, paren, arguments } => self.call_expr(*callee, paren, arguments),
+            Expr::Get { object, name } => self.get_expr(*object, name),
+            Expr::Set { object, name, value } => self.set_expr(*object, name, *value),
+            Expr::This { keyword } => self.look_up_variable(keyword, expr_idx),
+            Expr::Super { keyword, method } => self.super_expr(expr_idx, keyword, method),
+        }
+    }
+
+    fn unary_expr(&mut self, operator: &Token, right: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        let right = self.evaluate(right)?;
+        match operator.token_type {
+            TokenType::Minus => right.negate_if_num(),
+            _ => right.is_truthy().bang_if_bool(),
+        }
+        .map_err(|err| value_error(operator, err))
+    }
+
+    fn cast_expr(&mut self, target: &Token, expression: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        match (&target.token_type, self.evaluate(expression)?) {
+            // Without `int`, `int(...)` only drops the fraction.
+            (TokenType::Int, LoxValue::Number(num)) if self.untyped => Ok(LoxValue::Number(num.trunc())),
+            (TokenType::Int, LoxValue::Number(num)) => Ok(LoxValue::Integer(num as i32)),
+            (TokenType::Number, LoxValue::Integer(int)) => Ok(LoxValue::Number(int as f64)),
+            (_, value @ (LoxValue::Number(_) | LoxValue::Integer(_))) => Ok(value),
+            _ => {
+                let err = LoxValueError::IncorrectOperand("Operand must be a number.".into());
+                Err(RuntimeError::IncorrectOperand(target.clone(), err))
+            }
+        }
+    }
+
+    fn binary_expr(&mut self, left: ExprIdx, operator: &Token, right: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        let left = self.evaluate(left)?;
+        let right = self.evaluate(right)?;
+        match operator.token_type {
+            TokenType::EqualEqual => Ok(left.is_equal(right)),
+            TokenType::BangEqual => left.is_equal(right).bang_if_bool(),
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+                left.compare_if_num(right, operator.token_type.clone())
+            }
+            _ => left.math_if_num(right, &operator.token_type),
+        }
+        .map_err(|err| value_error(operator, err))
+    }
+
+    fn logical_expr(&mut self, left: ExprIdx, operator: &Token, right: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        let left = self.evaluate(left)?;
+        let truthy = left.is_truthy() == LoxValue::Boolean(true);
+        if (operator.token_type == TokenType::Or) == truthy {
+            Ok(left)
+        } else {
+            self.evaluate(right)
+        }
+    }
+
+    fn assign_expr(&mut self, expr_idx: ExprIdx, name: &Token, operator: Option<&Token>, value: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        let mut value = self.evaluate(value)?;
+        if let Some(operator) = operator {
+            let base_operator = match operator.token_type {
+                TokenType::PlusEqual | TokenType::PlusPlus => TokenType::Plus,
+                TokenType::MinusEqual | TokenType::MinusMinus => TokenType::Minus,
+                TokenType::StarEqual => TokenType::Star,
+                _ => TokenType::Slash,
+            };
+            value = self
+                .look_up_variable(name, expr_idx)?
+                .math_if_num(value, &base_operator)
+                .map_err(|err| value_error(operator, err))?;
+        }
+
+        let result = match self.locals.get(&expr_idx) {
+            Some(distance) => Environment::assign_at(Rc::clone(&self.environment), *distance, name.lexeme, value.clone(), self.symbol_table),
+            None => self.globals.borrow_mut().assign(name.lexeme, value.clone(), self.symbol_table),
+        };
+        result.map_err(|err| RuntimeError::AssignVariableError(name.clone(), err))?;
+        Ok(value)
+    }
+
+    fn call_expr(&mut self, callee: ExprIdx, paren: &Token, arguments: &[ExprIdx]) -> Result<LoxValue, RuntimeError> {
+        let callee = self.evaluate(callee)?;
+        let arguments = arguments
+            .iter()
+            .map(|argument| self.evaluate(*argument))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let callable = match callee {
+            LoxValue::Callable(callable) if !matches!(callable, LoxCallable::Instance(_)) => callable,
+            _ => return Err(RuntimeError::InterpreterPanic(paren.clone(), "Can only call functions and classes.".into())),
+        };
+        let arity = callable.arity(self.symbol_table);
+        if arguments.len() != arity {
+            let message = format!("Expected {} arguments but got {}.", arity, arguments.len());
+            return Err(RuntimeError::InterpreterPanic(paren.clone(), message));
+        }
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::InterpreterPanic(paren.clone(), "Call stack overflow.".into()));
+        }
+
+        self.call_depth += 1;
+        let result = callable.call(self, arguments);
+        self.call_depth -= 1;
+        result
+    }
+
+    fn get_expr(&mut self, object: ExprIdx, name: &Token) -> Result<LoxValue, RuntimeError> {
+        match self.evaluate(object)? {
+            LoxValue::Callable(LoxCallable::Instance(instance)) => LoxInstance::get(instance, name, self.symbol_table),
+            _ => Err(RuntimeError::InstanceError(name.clone(), "Only instances have properties.".into())),
+        }
+    }
+
+    fn set_expr(&mut self, object: ExprIdx, name: &Token, value: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        match self.evaluate(object)? {
+            LoxValue::Callable(LoxCallable::Instance(instance)) => {
+                let value = self.evaluate(value)?;
+                instance.borrow_mut().set(name.clone(), value.clone());
+                Ok(value)
+            }
+            _ => Err(RuntimeError::InstanceError(name.clone(), "Only instances have fields.".into())),
+        }
+    }
+
+    fn super_expr(&mut self, expr_idx: ExprIdx, keyword: &Token, method: &Token) -> Result<LoxValue, RuntimeError> {
+        let distance = *self.locals.get(&expr_idx).expect("'super' is always resolved.");
+        let (super_symbol, this_symbol) = (self.symbol_table.intern("super"), self.symbol_table.intern("this"));
+        let superclass = Environment::get_at(Rc::clone(&self.environment), distance, &super_symbol, self.symbol_table);
+        let instance = Environment::get_at(Rc::clone(&self.environment), distance - 1, &this_symbol, self.symbol_table);
+
+        match (superclass, instance) {
+            (Ok(LoxValue::Callable(LoxCallable::Class(superclass))), Ok(LoxValue::Callable(LoxCallable::Instance(instance)))) => {
+                match superclass.find_method(&method.lexeme) {
+                    Some(LoxCallable::Function(function)) => {
+                        let bound = function.bind(&instance, self.symbol_table);
+                        Ok(LoxValue::Callable(LoxCallable::Function(Rc::new(bound))))
+                    }
+                    _ => {
+                        let message = format!("Undefined property '{}'.", self.symbol_table.resolve(method.lexeme));
+                        Err(RuntimeError::InstanceError(method.clone(), message))
+                    }
+                }
+            }
+            _ => Err(RuntimeError::InterpreterPanic(keyword.clone(), "Couldn't resolve 'super'.".into())),
+        }
+    }
+
+    fn look_up_variable(&self, name: &Token, expr_idx: ExprIdx) -> Result<LoxValue, RuntimeError> {
+        match self.locals.get(&expr_idx) {
+            Some(distance) => Environment::get_at(Rc::clone(&self.environment), *distance, &name.lexeme, self.symbol_table),
+            None => self.globals.borrow().get(name.lexeme, self.symbol_table),
+        }
+        .map_err(|err| RuntimeError::UndefinedVariable(name.clone(), err))
+    }
+}
+
+fn value_error(operator: &Token, err: LoxValueError) -> RuntimeError {
+    match err {
+        LoxValueError::DivideByZero(_) => RuntimeError::DivideByZero(operator.clone(), err),
+        LoxValueError::IncorrectOperand(_) => RuntimeError::IncorrectOperand(operator.clone(), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lox::{self, LoxError};
+
+    fn interpret(source: &str) -> Result<String, LoxError> {
+        let mut out = Vec::new();
+        lox::interpret_source(source, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_untyped_script() {
+        let source = "
+            fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            var greeting = \"fib:\";
+            print greeting + \" \";
+            for (var i = 0; i < 10; i++) {
+                if (i % 2 == 1) continue;
+                if (i > 6) break;
+                print fib(i);
+            }
+            fun counter() { var count = 0; fun inc() { count += 1; return count; } return inc; }
+            var c = counter();
+            c();
+            print c() * 1.5;";
+        assert_eq!(interpret(source).unwrap(), "fib: \n0\n1\n3\n8\n3\n");
+    }
+
+    #[test]
+    fn test_numbers_are_doubles() {
+        let source = "
+            var a = 7 / 2;
+            print a == 3.5;
+            print 2147483647 + 1;
+            print int(a) + -a;
+            match (a * 2) { 7 => print \"seven\"; else => print \"other\"; }";
+        assert_eq!(interpret(source).unwrap(), "true\n2147483648\n-0.5\nseven\n");
+        let error = interpret("print 1 << 2;").unwrap_err();
+        assert!(matches!(error, LoxError::RuntimeError(msg) if msg == "[line 1] Undefined operator on numbers."));
+    }
+
+    #[test]
+    fn test_typed_programs_have_ints() {
+        let source = "
+            fn main() -> void {
+                let a = 7;
+                print(a / 2);
+                print(-a / 2 + (a & 3) + (1 << 4));
+                print(int(2.5) + int(num(a) / 2.0));
+            }";
+        let mut out = Vec::new();
+        lox::interpret_typed_source(source, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "3\n16\n5\n");
+    }
+
+    #[test]
+    fn test_classes() {
+        let source = "
+            class Animal { init(name) { this.name = name; } speak() { return this.name + \" makes a sound\"; } }
+            class Dog < Animal { speak() { return super.speak() + \", woof\"; } }
+            print Dog(\"Rex\").speak();";
+        assert_eq!(interpret(source).unwrap(), "Rex makes a sound, woof\n");
+    }
+
+    #[test]
+    fn test_typed_program_starts_at_main() {
+        let source = "fn main() -> void { let s: str = \"hi\"; match (s) { \"hi\" => print(s); else => print(\"no\"); } }";
+        assert_eq!(interpret(source).unwrap(), "hi\n");
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let error = interpret("var a = 1;\nprint a / 0;").unwrap_err();
+        assert!(matches!(error, LoxError::RuntimeError(msg) if msg == "[line 2] Division by zero."));
+        let error = interpret("print undefined;").unwrap_err();
+        assert!(matches!(error, LoxError::RuntimeError(msg) if msg == "[line 1] Undefined variable 'undefined'."));
+        assert!(matches!(interpret("fun f() { break; }"), Err(LoxError::Error(_))));
+    }
+}
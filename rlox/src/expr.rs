@@ -18,6 +18,10 @@ impl ExprPool {
     pub fn get_expr(&self, idx: ExprIdx) -> &Expr {
         &self.exprs[idx.0]
     }
+
+    pub fn replace_expr(&mut self, idx: ExprIdx, expr: Expr) {
+        self.exprs[idx.0] = expr;
+    }
 }
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
@@ -37,11 +41,17 @@ pub enum Expr {
         operator: Token,
         right: ExprIdx,
     },
+    Cast {
+        target: Token,
+        expression: ExprIdx,
+    },
     Variable {
         name: Token,
     },
     Assign {
         name: Token,
+        // Set for compound assignments (`+=`, `++`, ...), `None` for plain `=`.
+        operator: Option<Token>,
         value: ExprIdx,
     },
     Logical {
@@ -0,0 +1,96 @@
+// Formatter behind `rlox-jasm fmt`. Lines are re-indented by brace depth, trailing whitespace is
+// dropped and runs of blank lines shrink to one. Everything within a line stays as written, so
+// comments survive and nothing has to parse.
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Code,
+    String,
+    // Block comments nest, this is the depth.
+    Comment(usize),
+}
+
+pub fn format_source(source: &str) -> String {
+    let mut out = String::new();
+    let (mut depth, mut state, mut blank) = (0, State::Code, false);
+    for line in source.lines() {
+        // Lines continuing a string or a block comment are kept as they are.
+        if state != State::Code {
+            out.push_str(line.trim_end());
+            out.push('\n');
+            (depth, state) = scan(line, depth, state);
+            continue;
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        let closing = line.chars().take_while(|c| *c == '}').count();
+        out.push_str(&INDENT.repeat(depth.saturating_sub(closing)));
+        out.push_str(line);
+        out.push('\n');
+        (depth, state) = scan(line, depth, state);
+    }
+    out
+}
+
+// Brace depth and state at the end of `line`. Braces in strings and comments don't count.
+fn scan(line: &str, mut depth: usize, mut state: State) -> (usize, State) {
+    let chars: Vec<char> = line.chars().collect();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let next = chars.get(idx + 1).copied();
+        match (state, chars[idx], next) {
+            (State::String, '"', _) => state = State::Code,
+            (State::String, _, _) => {}
+            (State::Comment(nesting), '/', Some('*')) => {
+                state = State::Comment(nesting + 1);
+                idx += 1;
+            }
+            (State::Comment(nesting), '*', Some('/')) => {
+                state = if nesting == 1 { State::Code } else { State::Comment(nesting - 1) };
+                idx += 1;
+            }
+            (State::Comment(_), _, _) => {}
+            (State::Code, '/', Some('/')) => break,
+            (State::Code, '/', Some('*')) => {
+                state = State::Comment(1);
+                idx += 1;
+            }
+            (State::Code, '"', _) => state = State::String,
+            (State::Code, '{', _) => depth += 1,
+            (State::Code, '}', _) => depth = depth.saturating_sub(1),
+            (State::Code, _, _) => {}
+        }
+        idx += 1;
+    }
+    (depth, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reindents() {
+        let source = "\n\nfn main() -> void {   \nlet x = 1;\n\n\n  if (x > 0) {\n print(\"}\"); // }\n}\n    else { print(\"{\"); }\n}\n\n";
+        let expected = "fn main() -> void {\n    let x = 1;\n\n    if (x > 0) {\n        print(\"}\"); // }\n    }\n    else { print(\"{\"); }\n}\n";
+        assert_eq!(format_source(source), expected);
+        assert_eq!(format_source(expected), expected);
+    }
+
+    #[test]
+    fn test_keeps_strings_and_comments() {
+        let source = "fn main() -> void {\n/* a { comment\n   /* nested */ still }\n*/\nprint(\"two\n   lines {\");\n}\n";
+        let expected = "fn main() -> void {\n    /* a { comment\n   /* nested */ still }\n*/\n    print(\"two\n   lines {\");\n}\n";
+        assert_eq!(format_source(source), expected);
+    }
+}
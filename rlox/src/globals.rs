@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::environment::Environment;
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::evaluator::{Evaluator, RuntimeError};
 use crate::lox_value::{LoxCallable, LoxValue, NativeFunctions};
 
 pub fn define_globals(global_environment: &Rc<RefCell<Environment>>, symbol_table: &mut SymbolTable) {
@@ -29,7 +29,7 @@ impl Callable for ClockFunction {
 
     fn call(
         &self,
-        _interpreter: &mut Interpreter,
+        _evaluator: &mut Evaluator,
         _arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError> {
         let start = SystemTime::now();
@@ -59,7 +59,7 @@ impl Callable for InputFunction {
 
     fn call(
         &self,
-        _interpreter: &mut Interpreter,
+        _evaluator: &mut Evaluator,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError> {
         if let LoxValue::String(prompt) = &arguments[0] {
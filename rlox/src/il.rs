@@ -0,0 +1,460 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use rustc_hash::FxHashMap;
+
+use crate::lexer::token::{Hf64, Token};
+
+// The operand enums below are encoded by their declaration order in the VM bytecode, see `assembler`.
+
+// Operand size suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte,
+    Int,
+    Float,
+}
+
+impl Size {
+    pub const ALL: [Size; 3] = [Size::Byte, Size::Int, Size::Float];
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            Size::Byte => 1,
+            Size::Int | Size::Float => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Bl,
+    Dl,
+    Eax,
+    Ebx,
+    Ecx,
+    Sp,
+    Bp,
+    Flg,
+}
+
+impl Reg {
+    pub const ALL: [Reg; 8] = [Reg::Bl, Reg::Dl, Reg::Eax, Reg::Ebx, Reg::Ecx, Reg::Sp, Reg::Bp, Reg::Flg];
+}
+
+// Source and destination of `mcp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Memory {
+    Stack,
+    Heap,
+}
+
+impl Memory {
+    pub const ALL: [Memory; 2] = [Memory::Stack, Memory::Heap];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpMode {
+    Les,
+    Leq,
+    Grt,
+    Geq,
+    Equ,
+    Neq,
+}
+
+impl CmpMode {
+    pub const ALL: [CmpMode; 6] = [CmpMode::Les, CmpMode::Leq, CmpMode::Grt, CmpMode::Geq, CmpMode::Equ, CmpMode::Neq];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl ArithOp {
+    pub const ALL: [ArithOp; 10] = [
+        ArithOp::Add, ArithOp::Sub, ArithOp::Mul, ArithOp::Div, ArithOp::Mod,
+        ArithOp::And, ArithOp::Or, ArithOp::Xor, ArithOp::Shl, ArithOp::Shr,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constant {
+    Byte(u8),
+    Int(i32),
+    Float(Hf64),
+}
+
+impl Constant {
+    pub fn size(&self) -> Size {
+        match self {
+            Constant::Byte(_) => Size::Byte,
+            Constant::Int(_) => Size::Int,
+            Constant::Float(_) => Size::Float,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    // stc %t value: push a constant.
+    Stc(Constant),
+    // add %t ...: pop two values, push the result.
+    Arith(ArithOp, Size),
+    // add %t &a &b: `b = a op b` on registers, the suffix is omitted for flag registers.
+    ArithReg(ArithOp, Option<Size>, Reg, Reg),
+    // cmp %t %mode: compare the two topmost values into `&bl`.
+    Cmp(Size, CmpMode),
+    Cnv(Size, Size),
+    Inc(Size, Reg, usize),
+    Dcr(Size, Reg, usize),
+    // mov &reg: copy the stack top into a register.
+    MovTop(Reg),
+    MovReg(Reg, Reg),
+    MovImm(usize, Reg),
+    MovLabel(String, Reg),
+    Pop(Size),
+    // rda %t: push the value at `[&ebx]`.
+    Rda(Size),
+    // rda &reg: push a register.
+    RdaReg(Reg),
+    // ldc %t: store the stack top at `[&ebx]`.
+    Ldc(Size),
+    Mcp(Memory, Memory),
+    Alc,
+    Del,
+    Cnd(String),
+    Jmp(String),
+    JmpReg(Reg),
+    Cal(String),
+    // Calls into the runtime by address.
+    CalAddr(u32),
+    Ret,
+    // Inline string data.
+    Raw(String),
+    Label(String),
+    Comment(String),
+}
+
+impl Instr {
+    pub fn comment(text: impl Into<String>) -> Self {
+        Instr::Comment(text.into())
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Size::Byte => "%b",
+            Size::Int => "%i",
+            Size::Float => "%f",
+        })
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reg::Bl => "&bl",
+            Reg::Dl => "&dl",
+            Reg::Eax => "&eax",
+            Reg::Ebx => "&ebx",
+            Reg::Ecx => "&ecx",
+            Reg::Sp => "&sp",
+            Reg::Bp => "&bp",
+            Reg::Flg => "&flg",
+        })
+    }
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Memory::Stack => "%s",
+            Memory::Heap => "%h",
+        })
+    }
+}
+
+impl fmt::Display for CmpMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CmpMode::Les => "%les",
+            CmpMode::Leq => "%leq",
+            CmpMode::Grt => "%grt",
+            CmpMode::Geq => "%geq",
+            CmpMode::Equ => "%equ",
+            CmpMode::Neq => "%neq",
+        })
+    }
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ArithOp::Add => "add",
+            ArithOp::Sub => "sub",
+            ArithOp::Mul => "mul",
+            ArithOp::Div => "div",
+            ArithOp::Mod => "mod",
+            ArithOp::And => "and",
+            ArithOp::Or => "or",
+            ArithOp::Xor => "xor",
+            ArithOp::Shl => "shl",
+            ArithOp::Shr => "shr",
+        })
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Byte(value) => write!(f, "{}", value),
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Stc(value) => write!(f, "stc {} {}", value.size(), value),
+            Instr::Arith(op, size) => write!(f, "{} {}", op, size),
+            Instr::ArithReg(op, Some(size), a, b) => write!(f, "{} {} {} {}", op, size, a, b),
+            Instr::ArithReg(op, None, a, b) => write!(f, "{} {} {}", op, a, b),
+            Instr::Cmp(size, mode) => write!(f, "cmp {} {}", size, mode),
+            Instr::Cnv(from, to) => write!(f, "cnv {} {}", from, to),
+            Instr::Inc(size, reg, amount) => write!(f, "inc {} {} {}", size, reg, amount),
+            Instr::Dcr(size, reg, amount) => write!(f, "dcr {} {} {}", size, reg, amount),
+            Instr::MovTop(reg) => write!(f, "mov {}", reg),
+            Instr::MovReg(from, to) => write!(f, "mov {} {}", from, to),
+            Instr::MovImm(value, reg) => write!(f, "mov {} {}", value, reg),
+            Instr::MovLabel(label, reg) => write!(f, "mov {} {}", label, reg),
+            Instr::Pop(size) => write!(f, "pop {}", size),
+            Instr::Rda(size) => write!(f, "rda {}", size),
+            Instr::RdaReg(reg) => write!(f, "rda {}", reg),
+            Instr::Ldc(size) => write!(f, "ldc {}", size),
+            Instr::Mcp(from, to) => write!(f, "mcp {} {}", from, to),
+            Instr::Alc => f.write_str("alc"),
+            Instr::Del => f.write_str("del"),
+            Instr::Cnd(label) => write!(f, "cnd {}", label),
+            Instr::Jmp(label) => write!(f, "jmp {}", label),
+            Instr::JmpReg(reg) => write!(f, "jmp {}", reg),
+            Instr::Cal(name) => write!(f, "cal {}", name),
+            Instr::CalAddr(address) => write!(f, "cal {:#x}", address),
+            Instr::Ret => f.write_str("ret"),
+            Instr::Raw(text) => write!(f, "raw {} \"{}\" ;", text.len(), text),
+            Instr::Label(label) => write!(f, "{}:", label),
+            Instr::Comment(text) => write!(f, "#{}#", text),
+        }
+    }
+}
+
+// Position in the Lox source an instruction was generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<&Token> for SourceLocation {
+    fn from(token: &Token) -> Self {
+        SourceLocation { line: token.line, column: token.column }
+    }
+}
+
+// One line of generated IL, indented by the scope depth it was generated in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IlLine {
+    pub indent: usize,
+    pub instr: Instr,
+    pub location: Option<SourceLocation>,
+}
+
+// A local's slot in its function's frame, relative to `&bp`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    pub type_name: String,
+}
+
+// Locals of a function in declaration order. Sibling blocks reuse the same offsets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub slots: Vec<Slot>,
+}
+
+// Codegen collects the IL here instead of writing it out directly, so it can be optimized first.
+#[derive(Debug, Default, Clone)]
+pub struct Il {
+    pub lines: Vec<IlLine>,
+    // In the order the functions were generated, i.e. the order of their labels.
+    pub frames: Vec<Frame>,
+    // Tagged onto every pushed line, codegen moves it along while walking the AST.
+    location: Option<SourceLocation>,
+}
+
+impl Il {
+    pub fn new() -> Self {
+        Il { lines: Vec::new(), frames: Vec::new(), location: None }
+    }
+
+    // Accepts `Option`s too, so optional instructions can be generated inline.
+    pub fn push(&mut self, indent: usize, instr: impl Into<Option<Instr>>) {
+        if let Some(instr) = instr.into() {
+            self.lines.push(IlLine { indent, instr, location: self.location });
+        }
+    }
+
+    pub fn location(&self) -> Option<SourceLocation> {
+        self.location
+    }
+
+    pub fn set_location(&mut self, location: Option<SourceLocation>) {
+        self.location = location;
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        for line in &self.lines {
+            write!(out, "\n{}{}", "\t".repeat(line.indent), line.instr)?;
+        }
+        Ok(())
+    }
+
+    // `--annotate`: the Lox lines above the IL they produced, the frame layout above every
+    // function and the references to every label next to it. Annotations are IL comments.
+    pub fn write_annotated(&self, out: &mut impl Write, source: &str) -> io::Result<()> {
+        let source_lines: Vec<&str> = source.lines().collect();
+        let references = self.label_references();
+        let mut frames = self.frames.iter().peekable();
+        // Highest source line echoed so far, and the line of the previous instruction.
+        let (mut shown, mut current) = (0, None);
+        for line in &self.lines {
+            let tabs = "\t".repeat(line.indent);
+            if let Some(location) = line.location.filter(|location| Some(location.line) != current) {
+                current = Some(location.line);
+                // Moving forward echoes everything skipped on the way, moving back (e.g. to a
+                // `for` increment) repeats just that line.
+                let first = if location.line > shown { shown + 1 } else { location.line };
+                for number in first..=location.line {
+                    let text = source_lines.get(number - 1).map_or("", |text| text.trim());
+                    if !text.is_empty() {
+                        write!(out, "\n{}#{:>4} | {}#", tabs, number, text)?;
+                    }
+                }
+                shown = shown.max(location.line);
+            }
+            if let Instr::Label(label) = &line.instr {
+                if let Some(frame) = frames.next_if(|frame| &frame.function == label) {
+                    write!(out, "\n{}#frame of {}, {} bytes#", tabs, label, frame.slots.iter().map(|slot| slot.offset + slot.size).max().unwrap_or(0))?;
+                    for slot in &frame.slots {
+                        let unit = if slot.size == 1 { "byte" } else { "bytes" };
+                        write!(out, "\n{}#  &bp+{:<4} {}: {} ({} {})#", tabs, slot.offset, slot.name, slot.type_name, slot.size, unit)?;
+                    }
+                }
+                write!(out, "\n{}{}", tabs, line.instr)?;
+                if let Some(refs) = references.get(label.as_str()) {
+                    write!(out, " #<- {}#", refs.join(", "))?;
+                }
+                continue;
+            }
+            write!(out, "\n{}{}", tabs, line.instr)?;
+        }
+        Ok(())
+    }
+
+    // Every instruction that refers to a label, as `<mnemonic> <line>:<column>`.
+    fn label_references(&self) -> FxHashMap<&str, Vec<String>> {
+        let mut references: FxHashMap<&str, Vec<String>> = FxHashMap::default();
+        for line in &self.lines {
+            let (mnemonic, label) = match &line.instr {
+                Instr::Cnd(label) => ("cnd", label),
+                Instr::Jmp(label) => ("jmp", label),
+                Instr::Cal(label) => ("cal", label),
+                Instr::MovLabel(label, _) => ("mov", label),
+                _ => continue,
+            };
+            let reference = match line.location {
+                Some(location) => format!("{} {}:{}", mnemonic, location.line, location.column),
+                None => mnemonic.to_string(),
+            };
+            let refs = references.entry(label.as_str()).or_default();
+            if !refs.contains(&reference) {
+                refs.push(reference);
+            }
+        }
+        references
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_printing() {
+        let instrs = [
+            (Instr::Stc(Constant::Float(Hf64::try_from(1.5).unwrap())), "stc %f 1.5"),
+            (Instr::Stc(Constant::Int(-1)), "stc %i -1"),
+            (Instr::ArithReg(ArithOp::Add, Some(Size::Int), Reg::Ecx, Reg::Ebx), "add %i &ecx &ebx"),
+            (Instr::ArithReg(ArithOp::Or, None, Reg::Dl, Reg::Flg), "or &dl &flg"),
+            (Instr::Cmp(Size::Byte, CmpMode::Equ), "cmp %b %equ"),
+            (Instr::Dcr(Size::Int, Reg::Sp, 8), "dcr %i &sp 8"),
+            (Instr::Mcp(Memory::Heap, Memory::Stack), "mcp %h %s"),
+            (Instr::CalAddr(0), "cal 0x0"),
+            (Instr::Raw("hi".into()), "raw 2 \"hi\" ;"),
+            (Instr::Label("main".into()), "main:"),
+            (Instr::comment("return"), "#return#"),
+        ];
+        for (instr, text) in instrs {
+            assert_eq!(instr.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn test_annotated_listing() {
+        let at = |line, column| Some(SourceLocation { line, column });
+        let mut il = Il::new();
+        il.frames.push(Frame {
+            function: "main".into(),
+            slots: vec![Slot { name: "a".into(), offset: 0, size: 4, type_name: "int".into() }],
+        });
+        il.set_location(at(1, 4));
+        il.push(0, Instr::Label("main".into()));
+        il.set_location(at(3, 5));
+        il.push(1, Instr::Stc(Constant::Int(1)));
+        il.push(1, Instr::Label("_loop".into()));
+        il.set_location(at(4, 9));
+        il.push(1, Instr::Jmp("_loop".into()));
+
+        let mut out = Vec::new();
+        il.write_annotated(&mut out, "fn main() -> void {\n\n    let a = 1;\n    }").unwrap();
+        let expected = [
+            "",
+            "#   1 | fn main() -> void {#",
+            "#frame of main, 4 bytes#",
+            "#  &bp+0    a: int (4 bytes)#",
+            "main:",
+            "\t#   3 | let a = 1;#",
+            "\tstc %i 1",
+            "\t_loop: #<- jmp 4:9#",
+            "\t#   4 | }#",
+            "\tjmp _loop",
+        ];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n"));
+    }
+}
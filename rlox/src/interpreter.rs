@@ -1,102 +1,87 @@
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::Write;
 use std::mem::discriminant;
-use rustc_hash::FxHashMap;
 use std::rc::Rc;
 
-use crate::environment::{Environment, EnvironmentError};
 use crate::expr::{Expr, ExprIdx, ExprPool};
+use crate::il::{ArithOp, CmpMode, Constant, Frame, Il, Instr, Memory, Reg, Size, Slot, SourceLocation};
 use crate::lexer::token::Literal;
 use crate::lexer::token::Token;
 use crate::lexer::token::TokenType;
 use crate::lox::{LoxError};
 use crate::scope::{Scope, ScopeRef};
-use crate::stmt::Stmt;
-use crate::lox_value::{LoxValue, LoxValueError};
-use crate::symbol::{SymbolTable};
+use crate::stmt::{MatchArm, Stmt};
+use crate::lox_value::LoxValue;
+use crate::symbol::{Symbol, SymbolTable};
 
 macro_rules! generate {
    ($out:expr, $tab_count:expr, $($line:expr),* $(,)?) => {{
-        let tabs = "\t".repeat($tab_count);
+        let tabs = $tab_count;
         $(
-            if (!$line.is_empty()) {
-                write!($out, "\n{}{}", tabs, $line)?;
-            }
+            $out.push(tabs, $line);
         )* 
-        //write!($out, "\n")?;
         Ok(LoxValue::Void) as Result<LoxValue, LoxError>
     }};
 }
 
+// Matches with at least this many dense integer cases are compiled to jump tables.
+const JUMP_TABLE_MIN_CASES: usize = 4;
+// Encoded size of a `jmp <label>` instruction in the built-in assembler, the distance between two
+// jump table entries.
+pub const JUMP_TABLE_STRIDE: usize = 5;
+
 macro_rules! f {
     ($($tt:tt)*) => {
         format!($($tt)*)
     };
 }
 
-#[derive(Debug)]
-pub enum RuntimeError {
-    IncorrectOperand(Token, LoxValueError),
-    DivideByZero(Token, LoxValueError),
-    InterpreterPanic(Token, String),
-    InstanceError(Token, String),
-    UndefinedVariable(Token, EnvironmentError),
-    AssignVariableError(Token, EnvironmentError),
-    InputError(String),
-    CustomError(String),
-    Return(LoxValue),
-}
-
-impl RuntimeError {
-    pub fn get_info(self) -> (Token, String) {
-        match self {
-            RuntimeError::IncorrectOperand(token, lox_value_err)
-            | RuntimeError::DivideByZero(token, lox_value_err) => {
-                (token, lox_value_err.get_string())
-            }
-            RuntimeError::UndefinedVariable(token, environment_err)
-            | RuntimeError::AssignVariableError(token, environment_err) => {
-                (token, environment_err.get_string())
-            }
-            RuntimeError::InterpreterPanic(token, err_str)
-            | RuntimeError::InstanceError(token, err_str) => (token, err_str),
-            _ => panic!("Should not reach here!"),
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct Interpreter<'a> {
-    //globals: Rc<RefCell<Environment>>,
-    //environment: Rc<RefCell<Environment>>,
-    //locals: FxHashMap<ExprIdx, usize>,
     expr_pool: &'a ExprPool,
     pub symbol_table: &'a mut SymbolTable,
     counter: usize,
-    last_sym: String
+    last_sym: String,
+    // (continue label, break label, frame position at loop entry) of every loop enclosing the current statement.
+    loop_labels: Vec<(String, String, usize)>,
+    // Index into `Il::frames` of the function being generated.
+    frame: Option<usize>,
+    // Whether dense matches may use jump tables, see `CompileOptions::jump_tables`.
+    jump_tables: bool,
 }
 
 impl<'a> Interpreter<'a> {
-    pub fn new(expr_pool: &'a ExprPool, symbol_table: &'a mut SymbolTable/*, _: FxHashMap<ExprIdx, usize>*/) -> Self {
-        // Create a new global environment
-        //let globals = Environment::new();
-        //define_globals(&globals, symbol_table);
-
-        // Initially, the environment is the global environment
-        //let environment = Rc::clone(&globals);
-
+    pub fn new(expr_pool: &'a ExprPool, symbol_table: &'a mut SymbolTable) -> Self {
         Self {
-            //globals,
-            //environment,
-            //locals,
             expr_pool,
             symbol_table,
             counter: 0,
-            last_sym: String::new() 
+            last_sym: String::new(),
+            loop_labels: Vec::new(),
+            frame: None,
+            jump_tables: false,
         }
     }
 
+    pub fn with_jump_tables(mut self, jump_tables: bool) -> Self {
+        self.jump_tables = jump_tables;
+        self
+    }
+
+    // Continues the label numbering of an earlier run, so the code of both can be linked together.
+    pub fn with_label_counter(mut self, counter: usize) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    pub fn label_counter(&self) -> usize {
+        self.counter
+    }
+
+    // Type of an expression in `scope`. The code generated to find it out is thrown away.
+    pub fn expression_type(&mut self, expr: ExprIdx, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+        self.handle_expression(self.expr_pool.get_expr(expr), &mut Il::new(), scope)
+    }
+
     pub fn gen_label(&mut self, pre: &str) -> String {
         let c = self.counter;
         self.counter += 1;
@@ -104,24 +89,120 @@ impl<'a> Interpreter<'a> {
         f!("{}{}", pre, c)
     }
 
-    pub fn get_globals(&self) -> Rc<RefCell<Environment>> {
-        //Rc::clone(&self.globals)
-        unreachable!()
+    fn pop_locals(&self, out: &mut Il, tab_count: usize, size: usize) -> Result<LoxValue, LoxError> {
+        if size > 0 {
+            generate!(out, tab_count, Instr::Dcr(Size::Int, Reg::Sp, size))
+        } else { Ok(LoxValue::Void) }
+    }
+
+    // Adds a just declared local to the layout of the current frame.
+    fn record_slot(&self, out: &mut Il, name: Symbol, scope: &Scope) -> Result<(), LoxError> {
+        let (offset, size, value) = scope.get_var(name, self.symbol_table)?;
+        if let Some(frame) = self.frame.and_then(|idx| out.frames.get_mut(idx)) {
+            frame.slots.push(Slot { name: self.symbol_table.resolve(name).to_string(), offset, size, type_name: value.type_name(self.symbol_table) });
+        }
+        Ok(())
+    }
+
+    // Codegen errors are reported at the line of the token that caused them.
+    fn error_at(&self, token: &Token, message: &str) -> LoxError {
+        LoxError::CompilationError(f!("[line {}] {}", token.line, message))
+    }
+
+    // Integer literals are accepted where a `num` is expected and lowered to a float constant.
+    // Any other `int` expression needs an explicit `num(...)`.
+    // Conditions folding turned into `true` or `false`.
+    fn constant_condition(&self, condition: ExprIdx) -> Option<bool> {
+        match self.expr_pool.get_expr(condition) {
+            Expr::Literal { value: Literal::True } => Some(true),
+            Expr::Literal { value: Literal::False } => Some(false),
+            _ => None,
+        }
+    }
+
+    // Generates the branch that always runs in a scope of its own. The ones that never run are
+    // still type checked, but their IL is thrown away.
+    fn gen_constant_branches<'s>(&mut self, taken: Option<&Stmt>, dead: impl Iterator<Item = &'s Stmt>, out: &mut Il, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+        for stmt in dead {
+            let dead_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), None, None)));
+            let _ = self.gen_il(std::slice::from_ref(stmt), &mut Il::new(), Some(dead_scope))?;
+        }
+        let Some(taken) = taken else { return Ok(LoxValue::Void) };
+        let taken_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), Some(scope.borrow().gen()), None)));
+        let _ = self.gen_il(std::slice::from_ref(taken), out, Some(taken_scope.clone()))?;
+        let size = taken_scope.borrow().local_size();
+        self.pop_locals(out, scope.borrow().gen(), size)
+    }
+
+    fn gen_coerced(&mut self, expr: ExprIdx, expected: &LoxValue, out: &mut Il, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+        if let (LoxValue::Number(_), Some(value)) = (expected, self.int_literal(expr)) {
+            generate!(out, scope.borrow().gen(), Instr::comment("int literal as num"), Instr::Stc(Constant::Float(value.into())))?;
+            return Ok(LoxValue::Number(value as f64));
+        }
+        self.handle_expression(self.expr_pool.get_expr(expr), out, scope)
+    }
+
+    fn int_literal(&self, expr: ExprIdx) -> Option<i32> {
+        match self.expr_pool.get_expr(expr) {
+            Expr::Literal { value: Literal::Int(value) } => Some(*value),
+            Expr::Grouping { expression } => self.int_literal(*expression),
+            Expr::Unary { operator, right } if operator.token_type == TokenType::Minus => self.int_literal(*right).map(i32::wrapping_neg),
+            _ => None,
+        }
+    }
+
+    pub fn gen_il(&mut self, statements: &[Stmt], out: &mut Il, cur_scope: Option<ScopeRef>) -> Result<LoxValue, LoxError> {
+        // Whatever the enclosing statement generates after these keeps its own location.
+        let location = out.location();
+        let result = self.gen_statements(statements, out, cur_scope);
+        out.set_location(location);
+        result
     }
 
-    pub fn gen_il(&mut self, statements: &[Stmt], out: &mut File, cur_scope: Option<ScopeRef>) -> Result<LoxValue, LoxError> {
+    fn stmt_location(&self, stmt: &Stmt) -> Option<SourceLocation> {
+        match stmt {
+            Stmt::Expression { expression } | Stmt::Print { expression } => self.expr_location(self.expr_pool.get_expr(*expression)),
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => self.expr_location(self.expr_pool.get_expr(*condition)),
+            Stmt::Var { name, .. } | Stmt::Function { name, .. } | Stmt::Class { name, .. } => Some(name.into()),
+            Stmt::Match { keyword, .. }
+            | Stmt::Break { keyword }
+            | Stmt::Continue { keyword }
+            | Stmt::Return { keyword, .. }
+            | Stmt::Import { keyword, .. } => Some(keyword.into()),
+            Stmt::Block { .. } => None,
+        }
+    }
+
+    fn expr_location(&self, expr: &Expr) -> Option<SourceLocation> {
+        match expr {
+            Expr::Binary { operator, .. } | Expr::Logical { operator, .. } | Expr::Unary { operator, .. } => Some(operator.into()),
+            Expr::Cast { target, .. } => Some(target.into()),
+            Expr::Variable { name } | Expr::Assign { name, .. } | Expr::Get { name, .. } | Expr::Set { name, .. } => Some(name.into()),
+            Expr::Call { paren, .. } => Some(paren.into()),
+            Expr::This { keyword } | Expr::Super { keyword, .. } => Some(keyword.into()),
+            Expr::Grouping { expression } => self.expr_location(self.expr_pool.get_expr(*expression)),
+            Expr::Literal { .. } => None,
+        }
+    }
+
+    fn gen_statements(&mut self, statements: &[Stmt], out: &mut Il, cur_scope: Option<ScopeRef>) -> Result<LoxValue, LoxError> {
         let scope = match cur_scope {
             None => Rc::new(RefCell::new(Scope::new(None, None, None))),
             Some(s) => s
         };
         for statement in statements {
-            if scope.clone().borrow().gen() <= 1 && !matches!(statement, Stmt::Function{..}) {
+            if scope.clone().borrow().gen() <= 1 && !matches!(statement, Stmt::Function{..} | Stmt::Class{..} | Stmt::Import{..}) {
                 return Err(LoxError::CompilationError("Top level statements are not allowed.".into()));
             }
+            if let Some(location) = self.stmt_location(statement) {
+                out.set_location(Some(location));
+            }
 
             match statement {
                 Stmt::Expression { expression } => {
-                    let _ = self.handle_expression(self.expr_pool.get_expr(*expression), out, scope.clone())?;
+                    // Discard the value so that the stack only ever holds the locals of the frame.
+                    let val = self.handle_expression(self.expr_pool.get_expr(*expression), out, scope.clone())?;
+                    self.pop_locals(out, scope.borrow().gen(), val.size())?;
                 }
                 Stmt::Print { expression } => {
                     let expr = self.expr_pool.get_expr(*expression);
@@ -129,22 +210,22 @@ impl<'a> Interpreter<'a> {
                     match val {
                         LoxValue::String(_) | LoxValue::Variable(_, _) => {
                             generate!(out, scope.borrow().gen(), 
-                                "#Print#",
-                                "mov &ebx",
-                                "mov &ebx &eax",
-                                "rda %i",
-                                "mov &ecx",
-                                "pop %i",
-                                "mov &sp &ebx",
-                                "inc %i &ecx 4",
-                                "mcp %h %s",
-                                "add %i &ecx &sp",
-                                "mov 1 &dl",
-                                "or &dl &flg",
-                                "mov &ecx &bl",
-                                "cal 0x0",
-                                "dcr %b &flg 1",
-                                "pop %i",
+                                Instr::comment("Print"),
+                                Instr::MovTop(Reg::Ebx),
+                                Instr::MovReg(Reg::Ebx, Reg::Eax),
+                                Instr::Rda(Size::Int),
+                                Instr::MovTop(Reg::Ecx),
+                                Instr::Pop(Size::Int),
+                                Instr::MovReg(Reg::Sp, Reg::Ebx),
+                                Instr::Inc(Size::Int, Reg::Ecx, 4),
+                                Instr::Mcp(Memory::Heap, Memory::Stack),
+                                Instr::ArithReg(ArithOp::Add, Some(Size::Int), Reg::Ecx, Reg::Sp),
+                                Instr::MovImm(1, Reg::Dl),
+                                Instr::ArithReg(ArithOp::Or, None, Reg::Dl, Reg::Flg),
+                                Instr::MovReg(Reg::Ecx, Reg::Bl),
+                                Instr::CalAddr(0),
+                                Instr::Dcr(Size::Byte, Reg::Flg, 1),
+                                Instr::Pop(Size::Int),
                             )?;
                         }
                         _ => return Err(LoxError::CompilationError("Expected string".into()))
@@ -152,79 +233,165 @@ impl<'a> Interpreter<'a> {
                 },
                 Stmt::Var { name, initializer }  => 
                     if name.token_type == TokenType::Identifier {
-                        let expr = self.expr_pool.get_expr(initializer.unwrap());
-                        generate!(out, scope.borrow().gen(), format!("#variable {}#", self.symbol_table.resolve(name.lexeme)))?;
-                        let val = self.handle_expression(expr, out, scope.clone())?;
+                        generate!(out, scope.borrow().gen(), Instr::comment(f!("variable {}", self.symbol_table.resolve(name.lexeme))))?;
+                        let val = match initializer {
+                            Some(initializer) => self.gen_coerced(*initializer, &LoxValue::from(&name.literal), out, scope.clone())?,
+                            // Uninitialized declarations get a zeroed slot of the declared type. Strings get an
+                            // empty heap string instead, since assigning to a string frees the old one.
+                            None => match &name.literal {
+                                Literal::Str(_) => self.handle_expression(&Expr::Literal { value: Literal::Str(String::new()) }, out, scope.clone())?,
+                                Literal::Num(_) => { generate!(out, scope.borrow().gen(), Instr::comment("zeroed num"), Instr::Stc(Constant::Float(0.into())))?; LoxValue::Number(0.0) },
+                                Literal::Int(_) => { generate!(out, scope.borrow().gen(), Instr::comment("zeroed int"), Instr::Stc(Constant::Int(0)))?; LoxValue::Integer(0) },
+                                _ => { generate!(out, scope.borrow().gen(), Instr::comment("zeroed bool"), Instr::Stc(Constant::Byte(0)))?; LoxValue::Boolean(false) },
+                            }
+                        };
+                        // `Literal::Void` means the annotation was omitted and the type is inferred.
+                        let declared = LoxValue::from(&name.literal);
+                        if name.literal != Literal::Void && discriminant(&declared) != discriminant(&val) {
+                            return Err(LoxError::CompilationError(format!(
+                                "[line {}] Variable '{}' is declared as '{}' but initialized with '{}'.",
+                                name.line,
+                                self.symbol_table.resolve(name.lexeme),
+                                declared.type_name(self.symbol_table),
+                                val.type_name(self.symbol_table)
+                            )));
+                        }
+                        if val.size() == 0 {
+                            return Err(LoxError::CompilationError(format!(
+                                "[line {}] Can't initialize variable '{}' with a value of type '{}'.",
+                                name.line,
+                                self.symbol_table.resolve(name.lexeme),
+                                val.type_name(self.symbol_table)
+                            )));
+                        }
                         scope.borrow_mut().add_var(name.lexeme, val.size(), self.symbol_table, val)?;
+                        self.record_slot(out, name.lexeme, &scope.borrow())?;
                     } else { return Err(LoxError::CompilationError("Expected identifier".into())); },
                 Stmt::Block { statements } => { 
-                    generate!(out, scope.borrow().gen(), "#block#")?;
+                    generate!(out, scope.borrow().gen(), Instr::comment("block"))?;
                     let b_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), Some(scope.borrow().gen()), None)));
                     let _ = self.gen_il(&statements, out, Some(b_scope.clone()))?;
-                    generate!(out, scope.borrow().gen(), f!("dcr %i &sp {}", b_scope.borrow().pos()))?;
+                    self.pop_locals(out, scope.borrow().gen(), b_scope.borrow().local_size())?;
                 }
                 Stmt::If { condition, then_branch, else_branch} => {
+                    if let Some(value) = self.constant_condition(*condition) {
+                        let (taken, dead) = if value { (Some(then_branch), else_branch.as_ref()) } else { (else_branch.as_ref(), Some(then_branch)) };
+                        self.gen_constant_branches(taken.map(Rc::as_ref), dead.map(Rc::as_ref).into_iter(), out, scope.clone())?;
+                        continue;
+                    }
                     let if_br = self.gen_label("_if_"); 
                     let else_br = self.gen_label("_else_"); 
                     let if_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), None, None)));
-                    generate!(out, scope.borrow().gen(), "#if statement#")?;
+                    generate!(out, scope.borrow().gen(), Instr::comment("if statement"))?;
                     let conditional = self.handle_expression(self.expr_pool.get_expr(*condition), out, if_scope.clone())?;
                     if discriminant(&conditional) != discriminant(&LoxValue::Boolean(false)) {
                         return Err(LoxError::CompilationError("Expected boolean for the conditional.".into()))
                     }
                     generate!(out, if_scope.borrow().gen(),
-                        "mov &bl",
-                        "pop %b",
-                        f!("cnd {}", if_br),
-                        f!("jmp {}", else_br),
-                        f!("{}:", if_br)
+                        Instr::MovTop(Reg::Bl),
+                        Instr::Pop(Size::Byte),
+                        Instr::Cnd(if_br.clone()),
+                        Instr::Jmp(else_br.clone()),
+                        Instr::Label(if_br.clone())
                     )?;
                     let _ = self.gen_il(&[then_branch.as_ref().clone()], out, Some(if_scope.clone()))?;
-                    generate!(out, if_scope.borrow().gen(),
-                        f!("{}:", else_br)
-                    )?;
+                    self.pop_locals(out, if_scope.borrow().gen(), if_scope.borrow().local_size())?;
                     if let Some(else_branch) = else_branch {
-                        generate!(out, scope.borrow().gen(), "#else statement#")?;
+                        // The then-branch has to skip the else-branch, otherwise `else if` chains fall through.
+                        let if_end = self.gen_label("_if_end_");
+                        generate!(out, if_scope.borrow().gen(),
+                            Instr::Jmp(if_end.clone()),
+                            Instr::Label(else_br.clone())
+                        )?;
+                        generate!(out, scope.borrow().gen(), Instr::comment("else statement"))?;
                         let else_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), None, None)));
                         let _ = self.gen_il(&[else_branch.as_ref().clone()], out, Some(else_scope.clone()))?;
+                        self.pop_locals(out, else_scope.borrow().gen(), else_scope.borrow().local_size())?;
+                        generate!(out, scope.borrow().gen(), Instr::Label(if_end.clone()))?;
+                    } else {
+                        generate!(out, if_scope.borrow().gen(),
+                            Instr::Label(else_br.clone())
+                        )?;
                     }
                 },
-                Stmt::While { condition, body } => {
+                Stmt::Match { keyword: _, subject, arms, default } => {
+                    self.gen_match(*subject, arms, default.as_deref(), out, scope.clone())?;
+                },
+                Stmt::While { condition, body, increment } => {
+                    if self.constant_condition(*condition) == Some(false) {
+                        // The increment is only type checked, the same as the body.
+                        let dead_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), None, None)));
+                        if let Some(increment) = increment {
+                            self.handle_expression(self.expr_pool.get_expr(*increment), &mut Il::new(), dead_scope)?;
+                        }
+                        self.gen_constant_branches(None, std::iter::once(body.as_ref()), out, scope.clone())?;
+                        continue;
+                    }
                     let while_start = self.gen_label("_while_start_"); 
                     let while_body = self.gen_label("_while_body_");
+                    let while_continue = self.gen_label("_while_continue_");
                     let while_end = self.gen_label("_while_end_");
                     let while_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), None, None)));
-                    generate!(out, scope.borrow().gen(), "#while loop#", f!("{}:", while_start))?;
+                    generate!(out, scope.borrow().gen(), Instr::comment("while loop"), Instr::Label(while_start.clone()))?;
                     let condition_val = self.handle_expression(self.expr_pool.get_expr(*condition), out, while_scope.clone())?;
                     if discriminant(&condition_val) != discriminant(&LoxValue::Boolean(false)) {
                         return Err(LoxError::CompilationError("Expected boolean for the conditional.".into()))
                     }
                     generate!(out, while_scope.borrow().gen(),
-                        "mov &bl",
-                        "pop %b",
-                        f!("cnd {}", while_body),
-                        f!("jmp {}", while_end),
-                        f!("{}:", while_body)
+                        Instr::MovTop(Reg::Bl),
+                        Instr::Pop(Size::Byte),
+                        Instr::Cnd(while_body.clone()),
+                        Instr::Jmp(while_end.clone()),
+                        Instr::Label(while_body.clone())
                     )?;
-                    let _ = self.gen_il(&[body.as_ref().clone()], out, Some(while_scope.clone()))?;
+                    self.loop_labels.push((while_continue.clone(), while_end.clone(), while_scope.borrow().base()));
+                    let body_result = self.gen_il(&[body.as_ref().clone()], out, Some(while_scope.clone()));
+                    self.loop_labels.pop();
+                    let _ = body_result?;
+                    // Reclaim the body's locals every iteration, `continue` does the same before jumping here.
+                    self.pop_locals(out, while_scope.borrow().gen(), while_scope.borrow().local_size())?;
+                    generate!(out, while_scope.borrow().gen(), Instr::Label(while_continue.clone()))?;
+                    if let Some(increment) = increment {
+                        generate!(out, while_scope.borrow().gen(), Instr::comment("loop increment"))?;
+                        let val = self.handle_expression(self.expr_pool.get_expr(*increment), out, while_scope.clone())?;
+                        self.pop_locals(out, while_scope.borrow().gen(), val.size())?;
+                    }
                     generate!(out, while_scope.borrow().gen(),
-                        f!("jmp {}", while_start),
+                        Instr::Jmp(while_start.clone()),
                     )?;
                     generate!(out, scope.borrow().gen(),
-                        f!("{}:", while_end)
+                        Instr::Label(while_end.clone())
                     )?;
                 },
+                Stmt::Break { keyword } => match self.loop_labels.last() {
+                    Some((_, while_end, loop_base)) => {
+                        generate!(out, scope.borrow().gen(), Instr::comment("break"))?;
+                        self.pop_locals(out, scope.borrow().gen(), scope.borrow().pos() - loop_base)?;
+                        generate!(out, scope.borrow().gen(), Instr::Jmp(while_end.clone()))?;
+                    },
+                    None => return Err(self.error_at(keyword, "Can't use 'break' outside of a loop."))
+                },
+                Stmt::Continue { keyword } => match self.loop_labels.last() {
+                    Some((while_continue, _, loop_base)) => {
+                        generate!(out, scope.borrow().gen(), Instr::comment("continue"))?;
+                        self.pop_locals(out, scope.borrow().gen(), scope.borrow().pos() - loop_base)?;
+                        generate!(out, scope.borrow().gen(), Instr::Jmp(while_continue.clone()))?;
+                    },
+                    None => return Err(self.error_at(keyword, "Can't use 'continue' outside of a loop."))
+                },
                 Stmt::Function { name, params, return_type, body } => {
                     let var = self.symbol_table.resolve(name.lexeme);
                     generate!(out, scope.borrow().gen(), 
-                        "#function definition#",
-                        f!("#{}({}) -> {}#", var, params.len(), return_type),
-                        f!("{}:", var)
+                        Instr::comment("function definition"),
+                        Instr::comment(f!("{}({}) -> {}", var, params.len(), return_type)),
+                        Instr::Label(var.to_string())
                     )?;
                     let mut fn_scope = Scope::new(Some(scope.clone()), None, Some(name.lexeme));
+                    let enclosing_frame = self.frame.replace(out.frames.len());
+                    out.frames.push(Frame { function: var.to_string(), slots: Vec::new() });
                     let mut params_returns: Vec<LoxValue> = Vec::new();
                     if params.len() != 0 { 
-                        for Token { token_type: _, lexeme, literal, line: _ } in params {
+                        for Token { lexeme, literal, .. } in params {
                             let val: LoxValue = From::<&Literal>::from(literal);
                             fn_scope.add_var(
                                 *lexeme, 
@@ -232,56 +399,319 @@ impl<'a> Interpreter<'a> {
                                 self.symbol_table,
                                 val.clone()
                             )?;
+                            self.record_slot(out, *lexeme, &fn_scope)?;
                             params_returns.push(val);
                         }
                     }
                     let signature = (params.len(), return_type.clone(), Rc::new(RefCell::new(params_returns)));
                     scope.borrow_mut().add_signature(name.lexeme, &signature, self.symbol_table)?;
                     fn_scope.add_signature(name.lexeme, &signature, self.symbol_table)?;
-                    let _ = self.gen_il(&body, out, Some(Rc::new(RefCell::new(fn_scope))))?;
+                    // Loops don't extend into nested function bodies.
+                    let enclosing_loops = std::mem::take(&mut self.loop_labels);
+                    let fn_scope = Rc::new(RefCell::new(fn_scope));
+                    let body_result = self.gen_il(&body, out, Some(fn_scope.clone()));
+                    self.loop_labels = enclosing_loops;
+                    self.frame = enclosing_frame;
+                    let _ = body_result?;
+                    // `ret` discards the whole frame, so void functions just need one at the end.
+                    if return_type.size() == 0 && !matches!(body.last(), Some(Stmt::Return { .. })) {
+                        generate!(out, fn_scope.borrow().gen(),
+                            Instr::comment("implicit return"),
+                            Instr::MovImm(0, Reg::Bl),
+                            Instr::Ret
+                        )?;
+                    }
                 },
-                Stmt::Return { keyword: _, value } => {
+                Stmt::Return { keyword, value } => {
                     let borrowed_scope = scope.borrow();
+                    let fn_id = match borrowed_scope.fn_id() {
+                        Some(id) => id,
+                        None => return Err(self.error_at(keyword, "Can't return from top-level code."))
+                    };
+                    let fn_ret_type = borrowed_scope.get_signature(fn_id, self.symbol_table)?.1;
                     let ret_type: LoxValue = if let Some(exprid) = value {
-                        generate!(out, borrowed_scope.gen(), "#return eval#")?;
-                        let expr = self.expr_pool.get_expr(*exprid);    
-                        let val = self.handle_expression(expr, out, scope.clone());
-                        if let Err(e) = val {
-                            return Err(e)
-                        } else { val? }
+                        generate!(out, borrowed_scope.gen(), Instr::comment("return eval"))?;
+                        self.gen_coerced(*exprid, &fn_ret_type, out, scope.clone())?
                     } else { LoxValue::Void };
-                    let fn_ret_type = &borrowed_scope.get_signature(borrowed_scope.id().unwrap(), self.symbol_table)?.1;
-                    if discriminant(fn_ret_type) != discriminant(&ret_type) {
-                        return Err(LoxError::CompilationError("Return type doesn't match with function signature.".into()));
+                    if discriminant(&fn_ret_type) != discriminant(&ret_type) {
+                        return Err(self.error_at(keyword, "Return type doesn't match with function signature."));
                     }
                     generate!(out, scope.borrow().gen(),
-                        "#return#",
-                        f!("mov {} &bl", ret_type.size()),
-                        "ret"
+                        Instr::comment("return"),
+                        Instr::MovImm(ret_type.size(), Reg::Bl),
+                        Instr::Ret
                     )?;
                     return Ok(LoxValue::Void);
                 },
-                Stmt::Class { name: _, superclass: _, methods : _} => unreachable!("Classes are not yesupported."),
-                _ => unreachable!("This should've been unreachable")
+                Stmt::Class { name, superclass: _, methods : _} => return Err(self.error_at(name, "Classes are not supported yet.")),
+                // Modules are linked before codegen, nothing is left to generate.
+                Stmt::Import { keyword, .. } => if scope.borrow().gen() > 1 {
+                    return Err(self.error_at(keyword, "Imports are only allowed at the top level."));
+                },
             }
         }
 
-        //if !scope.has_parent() && scope.gen() > 2 {
-        if !scope.borrow().is_void() {
-            Err(LoxError::CompilationError("Expected return at the end of function body.".into()))
-        } else { Ok(LoxValue::Void) }
+        // Missing returns are caught by the control flow analysis before codegen.
+        Ok(LoxValue::Void)
+    }
+
+    fn check_patterns(&self, arms: &[MatchArm], subject_val: &LoxValue) -> Result<(), LoxError> {
+        let mut seen: Vec<&Literal> = Vec::new();
+        for pattern in arms.iter().flat_map(|arm| arm.patterns.iter()) {
+            if discriminant(&LoxValue::from(pattern)) != discriminant(subject_val) {
+                return Err(LoxError::CompilationError(format!("Match pattern of type '{}' doesn't match the subject type '{}'.", LoxValue::from(pattern).type_name(self.symbol_table), subject_val.type_name(self.symbol_table))));
+            }
+            if seen.contains(&pattern) {
+                return Err(LoxError::CompilationError("Duplicate pattern in match statement.".into()));
+            }
+            seen.push(pattern);
+        }
+        Ok(())
+    }
+
+    // A subject folding turned into a literal picks its arm at compile time.
+    fn gen_constant_match(&mut self, value: &Literal, arms: &[MatchArm], default: Option<&Stmt>, out: &mut Il, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+        self.check_patterns(arms, &LoxValue::from(value))?;
+        let taken = arms.iter().position(|arm| arm.patterns.iter().any(|pattern| same_constant(pattern, value)));
+        let bodies = arms.iter().map(|arm| arm.body.as_ref()).chain(default).collect::<Vec<_>>();
+        let taken_idx = taken.unwrap_or(arms.len());
+        let dead = bodies.iter().enumerate().filter(|(idx, _)| *idx != taken_idx).map(|(_, body)| *body);
+        self.gen_constant_branches(bodies.get(taken_idx).copied(), dead, out, scope)
     }
 
-    fn handle_expression(&mut self, expr: &Expr, out: &mut File, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+    fn gen_match(&mut self, subject: ExprIdx, arms: &[MatchArm], default: Option<&Stmt>, out: &mut Il, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+        if let Expr::Literal { value } = self.expr_pool.get_expr(subject) {
+            return self.gen_constant_match(value, arms, default, out, scope);
+        }
+        let match_scope = Rc::new(RefCell::new(Scope::new(Some(scope.clone()), None, None)));
+        generate!(out, scope.borrow().gen(), Instr::comment("match statement"))?;
+        let subject_val = self.handle_expression(self.expr_pool.get_expr(subject), out, match_scope.clone())?;
+        // Strings are matched by `gen_str_pattern`, their size is never used.
+        let size = match subject_val {
+            LoxValue::Integer(_) | LoxValue::String(_) => Size::Int,
+            LoxValue::Number(_) => Size::Float,
+            LoxValue::Boolean(_) => Size::Byte,
+            _ => return Err(LoxError::CompilationError(format!("Can't match on a value of type '{}'.", subject_val.type_name(self.symbol_table))))
+        };
+
+        // The subject lives in a hidden local so every case can reload it.
+        let subject_name = self.symbol_table.intern("$match");
+        let pos = match_scope.borrow().pos();
+        match_scope.borrow_mut().add_var(subject_name, subject_val.size(), self.symbol_table, subject_val.clone())?;
+        self.record_slot(out, subject_name, &match_scope.borrow())?;
+
+        self.check_patterns(arms, &subject_val)?;
+
+        let case_labels: Vec<String> = arms.iter().map(|_| self.gen_label("_match_case_")).collect();
+        let default_label = self.gen_label("_match_default_");
+        let match_end = self.gen_label("_match_end_");
+        let tabs = match_scope.borrow().gen();
+        let load_subject = [
+            Some(Instr::MovReg(Reg::Bp, Reg::Ebx)),
+            (pos > 0).then_some(Instr::Inc(Size::Int, Reg::Ebx, pos)),
+            Some(if subject_val.size() == 1 { Instr::Rda(Size::Byte) } else { Instr::Rda(Size::Int) }),
+        ];
+
+        let int_cases: Vec<(i32, usize)> = arms.iter().enumerate()
+            .flat_map(|(idx, arm)| arm.patterns.iter().filter_map(move |p| match p { Literal::Int(v) => Some((*v, idx)), _ => None }))
+            .collect();
+        let (min, max) = (int_cases.iter().map(|c| c.0).min().unwrap_or(0), int_cases.iter().map(|c| c.0).max().unwrap_or(0));
+        let span = max as i64 - min as i64 + 1;
+
+        if self.jump_tables && int_cases.len() >= JUMP_TABLE_MIN_CASES && span <= 2 * int_cases.len() as i64 {
+            // Dense integer cases: bounds check, then jump through a table of `jmp`s indexed by `subject - min`.
+            let table = self.gen_label("_match_table_");
+            generate!(out, tabs, Instr::comment("jump table"))?;
+            for (bound, mode) in [(min, CmpMode::Les), (max, CmpMode::Grt)] {
+                generate!(out, tabs, load_subject[0].clone(), load_subject[1].clone(), load_subject[2].clone(),
+                    Instr::Stc(Constant::Int(bound)),
+                    Instr::Cmp(Size::Int, mode),
+                    Instr::Pop(Size::Int),
+                    Instr::Pop(Size::Int),
+                    Instr::RdaReg(Reg::Bl),
+                    Instr::MovTop(Reg::Bl),
+                    Instr::Pop(Size::Byte),
+                    Instr::Cnd(default_label.clone())
+                )?;
+            }
+            generate!(out, tabs, load_subject[0].clone(), load_subject[1].clone(), load_subject[2].clone(),
+                Instr::Stc(Constant::Int(min)),
+                Instr::Arith(ArithOp::Sub, Size::Int),
+                Instr::Stc(Constant::Int(JUMP_TABLE_STRIDE as i32)),
+                Instr::Arith(ArithOp::Mul, Size::Int),
+                Instr::MovTop(Reg::Ecx),
+                Instr::Pop(Size::Int),
+                Instr::MovLabel(table.clone(), Reg::Ebx),
+                Instr::ArithReg(ArithOp::Add, Some(Size::Int), Reg::Ecx, Reg::Ebx),
+                Instr::JmpReg(Reg::Ebx),
+                Instr::Label(table.clone())
+            )?;
+            for value in min..=max {
+                let target = int_cases.iter().find(|c| c.0 == value).map_or(&default_label, |c| &case_labels[c.1]);
+                generate!(out, tabs, Instr::Jmp(target.clone()))?;
+            }
+        } else {
+            // Sparse cases: compare the subject against each pattern in order.
+            for (idx, arm) in arms.iter().enumerate() {
+                for pattern in &arm.patterns {
+                    generate!(out, tabs, Instr::comment(f!("case {}", LoxValue::from(pattern))))?;
+                    if let Literal::Str(text) = pattern {
+                        self.gen_str_pattern(text, &load_subject, &case_labels[idx], tabs, out)?;
+                        continue;
+                    }
+                    let (constant, pop) = match pattern {
+                        Literal::Int(v) => (Constant::Int(*v), Size::Int),
+                        Literal::Num(v) => (Constant::Float(*v), Size::Int),
+                        Literal::True => (Constant::Byte(1), Size::Byte),
+                        _ => (Constant::Byte(0), Size::Byte),
+                    };
+                    generate!(out, tabs, load_subject[0].clone(), load_subject[1].clone(), load_subject[2].clone(),
+                        Instr::Stc(constant),
+                        Instr::Cmp(size, CmpMode::Equ),
+                        Instr::Pop(pop),
+                        Instr::Pop(pop),
+                        Instr::RdaReg(Reg::Bl),
+                        Instr::MovTop(Reg::Bl),
+                        Instr::Pop(Size::Byte),
+                        Instr::Cnd(case_labels[idx].clone())
+                    )?;
+                }
+            }
+            generate!(out, tabs, Instr::Jmp(default_label.clone()))?;
+        }
+
+        let bodies = arms.iter().map(|arm| arm.body.as_ref()).zip(case_labels.iter());
+        for (body, label) in bodies.chain(default.into_iter().zip(std::iter::once(&default_label))) {
+            let case_scope = Rc::new(RefCell::new(Scope::new(Some(match_scope.clone()), None, None)));
+            generate!(out, tabs, Instr::Label(label.clone()))?;
+            let _ = self.gen_il(std::slice::from_ref(body), out, Some(case_scope.clone()))?;
+            self.pop_locals(out, case_scope.borrow().gen(), case_scope.borrow().local_size())?;
+            generate!(out, tabs, Instr::Jmp(match_end.clone()))?;
+        }
+        if default.is_none() {
+            generate!(out, tabs, Instr::Label(default_label.clone()))?;
+        }
+        generate!(out, tabs, Instr::Label(match_end.clone()))?;
+        let subject_size = match_scope.borrow().local_size();
+        self.pop_locals(out, scope.borrow().gen(), subject_size)
+    }
+
+    // Strings are compared by length and then byte by byte, unrolled over the literal pattern.
+    fn gen_str_pattern(&mut self, text: &str, load_subject: &[Option<Instr>; 3], case_label: &str, tabs: usize, out: &mut Il) -> Result<LoxValue, LoxError> {
+        let mismatch = self.gen_label("_str_mismatch_");
+        generate!(out, tabs, load_subject[0].clone(), load_subject[1].clone(), load_subject[2].clone(),
+            Instr::MovTop(Reg::Ebx),
+            Instr::Pop(Size::Int),
+            Instr::Rda(Size::Int),
+            Instr::Stc(Constant::Int(text.len() as i32)),
+            Instr::Cmp(Size::Int, CmpMode::Equ),
+            Instr::Pop(Size::Int),
+            Instr::Pop(Size::Int),
+            Instr::RdaReg(Reg::Bl),
+            Instr::MovTop(Reg::Bl),
+            Instr::Pop(Size::Byte)
+        )?;
+        for (idx, byte) in text.bytes().enumerate() {
+            let matched = self.gen_label("_str_match_");
+            generate!(out, tabs,
+                Instr::Cnd(matched.clone()),
+                Instr::Jmp(mismatch.clone()),
+                Instr::Label(matched.clone()),
+                Instr::Inc(Size::Int, Reg::Ebx, if idx == 0 { 4 } else { 1 }),
+                Instr::Rda(Size::Byte),
+                Instr::Stc(Constant::Byte(byte)),
+                Instr::Cmp(Size::Byte, CmpMode::Equ),
+                Instr::Pop(Size::Byte),
+                Instr::Pop(Size::Byte),
+                Instr::RdaReg(Reg::Bl),
+                Instr::MovTop(Reg::Bl),
+                Instr::Pop(Size::Byte)
+            )?;
+        }
+        generate!(out, tabs, Instr::Cnd(case_label.to_string()), Instr::Label(mismatch.clone()))
+    }
+
+    // Instructions of an expression are tagged with its own token, literals inherit the enclosing one.
+    fn handle_expression(&mut self, expr: &Expr, out: &mut Il, scope: ScopeRef) -> Result<LoxValue, LoxError> {
+        let location = out.location();
+        out.set_location(self.expr_location(expr).or(location));
+        let result = self.gen_expression(expr, out, scope);
+        out.set_location(location);
+        result
+    }
+
+    fn gen_expression(&mut self, expr: &Expr, out: &mut Il, scope: ScopeRef) -> Result<LoxValue, LoxError> {
         match expr {
             Expr::Binary { left, operator, right } => {
-                let lhs = self.handle_expression(self.expr_pool.get_expr(*left), out, scope.clone())?;
-                let rhs = self.handle_expression(self.expr_pool.get_expr(*right), out, scope.clone())?;
-                if lhs.r#type() != rhs.r#type() { return Err(LoxError::CompilationError("Operand type missmatch.".into())) }
+                // An int literal operand takes the type of a `num` on the other side.
+                let lhs = match self.int_literal(*left) {
+                    Some(_) if self.int_literal(*right).is_none() => {
+                        let rhs = self.expression_type(*right, scope.clone())?;
+                        self.gen_coerced(*left, &rhs, out, scope.clone())?
+                    }
+                    _ => self.handle_expression(self.expr_pool.get_expr(*left), out, scope.clone())?,
+                };
+                let rhs = self.gen_coerced(*right, &lhs, out, scope.clone())?;
+                if lhs.r#type() != rhs.r#type() { return Err(self.error_at(operator, "Operand type missmatch.")) }
+                // Floats are popped as ints, both are 4 bytes wide.
+                let (size, pop) = match lhs {
+                    LoxValue::Number(_) => (Size::Float, Size::Int),
+                    LoxValue::Integer(_) => (Size::Int, Size::Int),
+                    LoxValue::Boolean(_) if matches!(operator.token_type, TokenType::EqualEqual | TokenType::BangEqual) => (Size::Byte, Size::Byte),
+                    _ => return Err(self.error_at(operator, &f!("Operator '{}' expects 'num' or 'int' operands, got '{}'.", self.symbol_table.resolve(operator.lexeme), lhs.type_name(self.symbol_table))))
+                };
                 match operator.token_type {
-                    TokenType::Plus => {generate!(out, scope.borrow().gen(), "add %f")?; Ok(lhs)},
-                    TokenType::Less => { generate!(out, scope.borrow().gen(), "cmp %f %les", "pop %i", "pop %i", "rda &bl")?; Ok(LoxValue::Boolean(false)) }
-                    _ => todo!("Not Yet")
+                    TokenType::Plus => { generate!(out, scope.borrow().gen(), Instr::Arith(ArithOp::Add, size))?; Ok(lhs) },
+                    TokenType::Minus => { generate!(out, scope.borrow().gen(), Instr::Arith(ArithOp::Sub, size))?; Ok(lhs) },
+                    TokenType::Star => { generate!(out, scope.borrow().gen(), Instr::Arith(ArithOp::Mul, size))?; Ok(lhs) },
+                    TokenType::Slash => { generate!(out, scope.borrow().gen(), Instr::Arith(ArithOp::Div, size))?; Ok(lhs) },
+                    TokenType::Percent
+                    | TokenType::Ampersand
+                    | TokenType::Pipe
+                    | TokenType::Caret
+                    | TokenType::LessLess
+                    | TokenType::GreaterGreater => {
+                        if !matches!(lhs, LoxValue::Integer(_)) {
+                            return Err(self.error_at(operator, &f!("Operator '{}' expects 'int' operands, got '{}'.", self.symbol_table.resolve(operator.lexeme), lhs.type_name(self.symbol_table))));
+                        }
+                        let op = match operator.token_type {
+                            TokenType::Percent => ArithOp::Mod,
+                            TokenType::Ampersand => ArithOp::And,
+                            TokenType::Pipe => ArithOp::Or,
+                            TokenType::Caret => ArithOp::Xor,
+                            TokenType::LessLess => ArithOp::Shl,
+                            _ => ArithOp::Shr,
+                        };
+                        generate!(out, scope.borrow().gen(), Instr::Arith(op, Size::Int))?;
+                        Ok(lhs)
+                    },
+                    TokenType::Less
+                    | TokenType::LessEqual
+                    | TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::EqualEqual
+                    | TokenType::BangEqual => {
+                        let mode = match operator.token_type {
+                            TokenType::Less => CmpMode::Les,
+                            TokenType::LessEqual => CmpMode::Leq,
+                            TokenType::Greater => CmpMode::Grt,
+                            TokenType::GreaterEqual => CmpMode::Geq,
+                            TokenType::EqualEqual => CmpMode::Equ,
+                            _ => CmpMode::Neq,
+                        };
+                        generate!(out, scope.borrow().gen(), Instr::Cmp(size, mode), Instr::Pop(pop), Instr::Pop(pop), Instr::RdaReg(Reg::Bl))?;
+                        Ok(LoxValue::Boolean(false))
+                    }
+                    _ => Err(self.error_at(operator, &f!("Unsupported binary operator '{}'.", self.symbol_table.resolve(operator.lexeme))))
+                }
+            },
+            Expr::Cast { target, expression } => {
+                let val = self.handle_expression(self.expr_pool.get_expr(*expression), out, scope.clone())?;
+                match (&target.token_type, &val) {
+                    (TokenType::Int, LoxValue::Integer(_)) | (TokenType::Number, LoxValue::Number(_)) => Ok(val),
+                    (TokenType::Int, LoxValue::Number(_)) => { generate!(out, scope.borrow().gen(), Instr::comment("num to int"), Instr::Cnv(Size::Float, Size::Int))?; Ok(LoxValue::Integer(0)) },
+                    (TokenType::Number, LoxValue::Integer(_)) => { generate!(out, scope.borrow().gen(), Instr::comment("int to num"), Instr::Cnv(Size::Int, Size::Float))?; Ok(LoxValue::Number(0.0)) },
+                    _ => Err(self.error_at(target, &f!("Can't convert '{}' to '{}'.", val.type_name(self.symbol_table), self.symbol_table.resolve(target.lexeme))))
                 }
             },
             Expr::Grouping { expression } => self.handle_expression(self.expr_pool.get_expr(*expression), out, scope.clone()),
@@ -289,124 +719,357 @@ impl<'a> Interpreter<'a> {
                 Literal::Str(val) =>
                     if !val.is_ascii() { Err(LoxError::CompilationError("Only ASCII strings are accepted.".to_string())) } 
                     else { generate!(out, scope.borrow().gen(),
-                                "#str literal#",
-                                f!("raw {} \"{}\" ;", val.len(), val),
-                                f!("mov {} &ecx", val.len()+4), 
-                                "alc", 
-                                "mov &ecx &eax",
-                                "sub %i &sp &eax",
-                                "mcp %s %h",
-                                f!("dcr %i &sp {}", val.len()+4), 
-                                "rda &ebx"
+                                Instr::comment("str literal"),
+                                Instr::Raw(val.clone()),
+                                Instr::MovImm(val.len()+4, Reg::Ecx),
+                                Instr::Alc, 
+                                Instr::MovReg(Reg::Ecx, Reg::Eax),
+                                Instr::ArithReg(ArithOp::Sub, Some(Size::Int), Reg::Sp, Reg::Eax),
+                                Instr::Mcp(Memory::Stack, Memory::Heap),
+                                Instr::Dcr(Size::Int, Reg::Sp, val.len()+4), 
+                                Instr::RdaReg(Reg::Ebx)
                             )?; Ok(LoxValue::String(val.to_string())) },
-                Literal::Num(val) => { generate!(out, scope.borrow().gen(), "#num literal#", f!("stc %f {}", val))?; Ok(LoxValue::Number(val.into())) },
-                Literal::True => { generate!(out, scope.borrow().gen(), "#bool literal#", "stc %b 1")?; Ok(LoxValue::Boolean(true)) },
-                Literal::False => { generate!(out, scope.borrow().gen(), "#bool literal#", "stc %b 0")?; Ok(LoxValue::Boolean(false)) }
-                _ => unreachable!("Void values are not implemented yet.")
+                Literal::Num(val) => { generate!(out, scope.borrow().gen(), Instr::comment("num literal"), Instr::Stc(Constant::Float(*val)))?; Ok(LoxValue::Number(val.into())) },
+                Literal::Int(val) => { generate!(out, scope.borrow().gen(), Instr::comment("int literal"), Instr::Stc(Constant::Int(*val)))?; Ok(LoxValue::Integer(*val)) },
+                Literal::True => { generate!(out, scope.borrow().gen(), Instr::comment("bool literal"), Instr::Stc(Constant::Byte(1)))?; Ok(LoxValue::Boolean(true)) },
+                Literal::False => { generate!(out, scope.borrow().gen(), Instr::comment("bool literal"), Instr::Stc(Constant::Byte(0)))?; Ok(LoxValue::Boolean(false)) }
+                Literal::Void => Err(LoxError::CompilationError("'nil' values are not supported.".into())),
+                Literal::Class(name) => Err(LoxError::CompilationError(f!("Type '{}' can't be used as a value.", self.symbol_table.resolve(*name))))
+            },
+            Expr::Unary { operator, right } => {
+                let val = self.handle_expression(self.expr_pool.get_expr(*right), out, scope.clone())?;
+                match (&operator.token_type, &val) {
+                    (TokenType::Minus, LoxValue::Integer(_)) => generate!(out, scope.borrow().gen(), Instr::comment("negate"), Instr::Stc(Constant::Int(-1)), Instr::Arith(ArithOp::Mul, Size::Int))?,
+                    (TokenType::Minus, LoxValue::Number(_)) => generate!(out, scope.borrow().gen(), Instr::comment("negate"), Instr::Stc(Constant::Float((-1).into())), Instr::Arith(ArithOp::Mul, Size::Float))?,
+                    (TokenType::Bang, LoxValue::Boolean(_)) => generate!(out, scope.borrow().gen(), Instr::comment("not"), Instr::Stc(Constant::Byte(0)), Instr::Cmp(Size::Byte, CmpMode::Equ), Instr::Pop(Size::Byte), Instr::Pop(Size::Byte), Instr::RdaReg(Reg::Bl))?,
+                    _ => return Err(self.error_at(operator, &f!("Operator '{}' can't be applied to a value of type '{}'.", self.symbol_table.resolve(operator.lexeme), val.type_name(self.symbol_table))))
+                };
+                Ok(val)
             },
-            Expr::Unary { operator, right } => Ok(LoxValue::Void),
             Expr::Variable { name } => match name.token_type {
                 TokenType::Identifier => {
                     let var_name = self.symbol_table.resolve(name.lexeme);
                     if scope.borrow().has_var(name.lexeme) {
                         let (pos, size, var_t) = scope.borrow().get_var(name.lexeme, self.symbol_table)?;
                         generate!(out, scope.borrow().gen(), 
-                            f!("#var ref {}#", var_name),
-                            "mov &bp &ebx",
-                            if pos > 0 { f!("inc %i &ebx {}", pos) } else { "".into() },
-                            if size == 1 { "rda %b" } else { "rda %i" },
+                            Instr::comment(f!("var ref {}", var_name)),
+                            Instr::MovReg(Reg::Bp, Reg::Ebx),
+                            (pos > 0).then_some(Instr::Inc(Size::Int, Reg::Ebx, pos)),
+                            if size == 1 { Instr::Rda(Size::Byte) } else { Instr::Rda(Size::Int) },
                         )?;
                         Ok(var_t)
                     } else if scope.borrow().has_signature(name.lexeme) {
-                        generate!(out, scope.borrow().gen(), f!("#fn ref {}#", var_name))?;
+                        generate!(out, scope.borrow().gen(), Instr::comment(f!("fn ref {}", var_name)))?;
                         Ok(LoxValue::Fn(name.lexeme))
                     } else {
-                        Err(LoxError::CompilationError(format!("Couldn't find '{}' in the current scope", var_name)))
+                        Err(self.error_at(name, &f!("Couldn't find '{}' in the current scope", var_name)))
                     }
                 },
-                _ => Err(LoxError::CompilationError("Expected identifier".into()))
+                _ => Err(self.error_at(name, "Expected identifier"))
+            },
+            Expr::Assign { name: name @ Token { token_type: TokenType::Identifier, lexeme, .. }, operator: Some(operator), value } => {
+                let expr = self.expr_pool.get_expr(*value);
+                let (pos, _, var) = scope.borrow().get_var(*lexeme, self.symbol_table)?;
+                let size = match var {
+                    LoxValue::Number(_) if !matches!(operator.token_type, TokenType::PlusPlus | TokenType::MinusMinus) => Size::Float,
+                    LoxValue::Integer(_) => Size::Int,
+                    _ => return Err(self.error_at(operator, &f!("Operator '{}' can't be applied to a variable of type '{}'.", self.symbol_table.resolve(operator.lexeme), var.type_name(self.symbol_table))))
+                };
+                let op = match operator.token_type {
+                    TokenType::PlusEqual | TokenType::PlusPlus => ArithOp::Add,
+                    TokenType::MinusEqual | TokenType::MinusMinus => ArithOp::Sub,
+                    TokenType::StarEqual => ArithOp::Mul,
+                    _ => ArithOp::Div,
+                };
+                // The slot address is computed once. Literals don't touch &ebx, so it can stay there,
+                // anything else may call or use &ebx itself and the address is kept under the value.
+                let in_place = matches!(expr, Expr::Literal { .. });
+                generate!(out, scope.borrow().gen(),
+                    Instr::comment(f!("compound assignment {}", pos)),
+                    Instr::MovReg(Reg::Bp, Reg::Ebx),
+                    (pos > 0).then_some(Instr::Inc(Size::Int, Reg::Ebx, pos)),
+                    (!in_place).then_some(Instr::RdaReg(Reg::Ebx)),
+                    Instr::Rda(Size::Int),
+                )?;
+                let val = self.gen_coerced(*value, &var, out, scope.clone())?;
+                if var.r#type() != val.r#type() {
+                    return Err(self.error_at(name, "Type missmatch."));
+                }
+                generate!(out, scope.borrow().gen(), Instr::Arith(op, size))?;
+                if !in_place {
+                    generate!(out, scope.borrow().gen(),
+                        Instr::MovTop(Reg::Ecx),
+                        Instr::Pop(Size::Int),
+                        Instr::MovTop(Reg::Ebx),
+                        Instr::Pop(Size::Int),
+                        Instr::RdaReg(Reg::Ecx),
+                    )?;
+                }
+                generate!(out, scope.borrow().gen(), Instr::Ldc(Size::Int))?;
+                Ok(val)
             },
-            Expr::Assign { name: Token { token_type: TokenType::Identifier, lexeme, literal:_, line:_ }, value } => {
-                let expr = self.expr_pool.get_expr(*value); 
+            Expr::Assign { name: name @ Token { token_type: TokenType::Identifier, lexeme, .. }, operator: None, value } => {
                 let (pos, _, var) = scope.borrow().get_var(*lexeme, self.symbol_table)?;
-                generate!(out, scope.borrow().gen(), f!("#assignment {}#", pos))?;
-                let val = self.handle_expression(expr, out, scope.clone())?;
+                generate!(out, scope.borrow().gen(), Instr::comment(f!("assignment {}", pos)))?;
+                let val = self.gen_coerced(*value, &var, out, scope.clone())?;
                 if var.r#type() != val.r#type() {
-                    return Err(LoxError::CompilationError("Type missmatch.".into()));
+                    return Err(self.error_at(name, "Type missmatch."));
                 }
                 match val {
                     LoxValue::String(_) => {
                         generate!(out, scope.borrow().gen(),
-                            "#string assignment#",
-                            "mov &bp &ebx",
-                            if pos > 0 { f!("inc %i &ebx {}", pos) } else { "".into() },
-                            "rda %i",
-                            "mov &ebx",
-                            "pop %i",
-                            "rda %i",
-                            "mov &ecx",
-                            "pop %i",
-                            "inc %i &ecx 4",
-                            "del",
-                            "mov &bp &ebx",
-                            if pos > 0 { f!("inc %i &ebx {}", pos) } else { "".into() },
-                            "ldc %i",
-                            "pop %i"
+                            Instr::comment("string assignment"),
+                            Instr::MovReg(Reg::Bp, Reg::Ebx),
+                            (pos > 0).then_some(Instr::Inc(Size::Int, Reg::Ebx, pos)),
+                            Instr::Rda(Size::Int),
+                            Instr::MovTop(Reg::Ebx),
+                            Instr::Pop(Size::Int),
+                            Instr::Rda(Size::Int),
+                            Instr::MovTop(Reg::Ecx),
+                            Instr::Pop(Size::Int),
+                            Instr::Inc(Size::Int, Reg::Ecx, 4),
+                            Instr::Del,
+                            Instr::MovReg(Reg::Bp, Reg::Ebx),
+                            (pos > 0).then_some(Instr::Inc(Size::Int, Reg::Ebx, pos)),
+                            Instr::Ldc(Size::Int)
                         )?;
                         Ok(val)
                     },
-                    LoxValue::Void => Err(LoxError::CompilationError("Void assignation is not permitted.".into())),
-                    LoxValue::Callable(_) => Err(LoxError::CompilationError("Can't assign functions to things.".into())),
+                    LoxValue::Void => Err(self.error_at(name, "Void assignation is not permitted.")),
+                    LoxValue::Callable(_) => Err(self.error_at(name, "Can't assign functions to things.")),
                     _ => {
                         generate!(out, scope.borrow().gen(), 
-                            "#assignment#",
-                            "mov &bp &ebx",
-                            if pos > 0 { f!("inc %i &ebx {}", pos) } else { "".into() },
-                            if val.size() == 1 { "ldc %b" } else { "ldc %i" },
-                            if val.size() == 1 { "pop %b" } else { "pop %i" },
+                            Instr::comment("assignment"),
+                            Instr::MovReg(Reg::Bp, Reg::Ebx),
+                            (pos > 0).then_some(Instr::Inc(Size::Int, Reg::Ebx, pos)),
+                            if val.size() == 1 { Instr::Ldc(Size::Byte) } else { Instr::Ldc(Size::Int) },
                         )?;
                         Ok(val)
                     }
                 }
             },
-            Expr::Logical { left: _, operator: _, right : _} => Ok(LoxValue::Void),
-            Expr::Call { callee, paren: _, arguments } => {
+            Expr::Logical { left, operator, right } => {
+                let lhs = self.handle_expression(self.expr_pool.get_expr(*left), out, scope.clone())?;
+                if discriminant(&lhs) != discriminant(&LoxValue::Boolean(false)) {
+                    return Err(self.error_at(operator, "Expected boolean operands for logical operator."))
+                }
+                let logic_end = self.gen_label("_logic_end_");
+                // The left operand stays on the stack as the result when it decides the outcome.
+                if operator.token_type == TokenType::Or {
+                    generate!(out, scope.borrow().gen(),
+                        Instr::comment("logical or"),
+                        Instr::MovTop(Reg::Bl),
+                        Instr::Cnd(logic_end.clone()),
+                        Instr::Pop(Size::Byte)
+                    )?;
+                } else {
+                    let logic_rhs = self.gen_label("_logic_rhs_");
+                    generate!(out, scope.borrow().gen(),
+                        Instr::comment("logical and"),
+                        Instr::MovTop(Reg::Bl),
+                        Instr::Cnd(logic_rhs.clone()),
+                        Instr::Jmp(logic_end.clone()),
+                        Instr::Label(logic_rhs.clone()),
+                        Instr::Pop(Size::Byte)
+                    )?;
+                }
+                let rhs = self.handle_expression(self.expr_pool.get_expr(*right), out, scope.clone())?;
+                if discriminant(&rhs) != discriminant(&LoxValue::Boolean(false)) {
+                    return Err(self.error_at(operator, "Expected boolean operands for logical operator."))
+                }
+                generate!(out, scope.borrow().gen(), Instr::Label(logic_end.clone()))?;
+                Ok(LoxValue::Boolean(false))
+            },
+            Expr::Call { callee, paren, arguments } => {
                 let calid = self.expr_pool.get_expr(*callee);
                 let val = self.handle_expression(calid, out, scope.clone())?;
                 let mut size = 0;
                 if let LoxValue::Fn(name) = val {
-                    generate!(out, scope.borrow().gen(), "#function call#")?;
+                    generate!(out, scope.borrow().gen(), Instr::comment("function call"))?;
                     let fn_sign = scope.borrow().get_signature(name, self.symbol_table)?;
                     if fn_sign.0 != arguments.len() {
-                        Err(LoxError::CompilationError("Missmatching parameter count.".into()))
+                        Err(self.error_at(paren, "Missmatching parameter count."))
                     } else {
-                        if fn_sign.0 != 0 { generate!(out, scope.borrow().gen(), "#parameters#")?; }
-                        for (idx, exprid) in arguments.into_iter().enumerate() {
-                            let param = self.handle_expression(self.expr_pool.get_expr(*exprid), out, scope.clone())?;
-                            if discriminant(&param) != discriminant(fn_sign.2.borrow().get(idx).unwrap()){
-                                return Err(LoxError::CompilationError("Missmatching parameter types.".into()))
+                        if fn_sign.0 != 0 { generate!(out, scope.borrow().gen(), Instr::comment("parameters"))?; }
+                        for (idx, exprid) in arguments.iter().enumerate() {
+                            let expected = fn_sign.2.borrow().get(idx).cloned().unwrap_or(LoxValue::Void);
+                            let param = self.gen_coerced(*exprid, &expected, out, scope.clone())?;
+                            if discriminant(&param) != discriminant(&expected) {
+                                return Err(self.error_at(paren, "Missmatching parameter types."))
                             }
                             if size + param.size() > 255 {
-                                return Err(LoxError::CompilationError("Parameter size is too big (max 255)".into()))
+                                return Err(self.error_at(paren, "Parameter size is too big (max 255)"))
                             }
                             size += param.size();
                         }
                         generate!(out, scope.borrow().gen(),
-                            "#call#",
-                            f!("mov {} &bl", size),
-                            f!("cal {}", self.symbol_table.resolve(name))
+                            Instr::comment("call"),
+                            Instr::MovImm(size, Reg::Bl),
+                            Instr::Cal(self.symbol_table.resolve(name).to_string())
                         )?;
                         Ok(fn_sign.1)
                     }
                 } else {
-                    Err(LoxError::CompilationError("Attempt to call non-function value.".into()))
+                    Err(self.error_at(paren, "Attempt to call non-function value."))
                 }
             },
-            Expr::Get { object: _, name : _} => todo!("Classes are not yet supported."),
-            Expr::Set { object: _, name: _, value : _} => todo!("Classes are not yet supported."),
-            Expr::This { keyword : _} => todo!("Classes are not yet supported."),
-            Expr::Super { keyword: _, method : _} => todo!("Classes are not yet supported."),
-            _ => todo!()
+            Expr::Assign { name, .. } => Err(self.error_at(name, "Invalid assignment target.")),
+            Expr::Get { object: _, name } | Expr::Set { object: _, name, value : _} => Err(self.error_at(name, "Classes are not supported yet.")),
+            Expr::This { keyword } | Expr::Super { keyword, method : _} => Err(self.error_at(keyword, "Classes are not supported yet.")),
+        }
+    }
+}
+
+// Numbers are compared as the `f32`s they are at runtime, their decimals may differ in digits `f32` doesn't have.
+fn same_constant(pattern: &Literal, value: &Literal) -> bool {
+    match (pattern, value) {
+        (Literal::Num(a), Literal::Num(b)) => f64::from(a) as f32 == f64::from(b) as f32,
+        _ => pattern == value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+
+    use super::*;
+    use crate::lox::{self, CompileOptions};
+
+    fn compile(source: &str) -> Il {
+        lox::compile_il(source, &CompileOptions::default()).unwrap()
+    }
+
+    // Bytes an instruction pushes, negative for pops. Calls count as taking their arguments and
+    // leaving a value of the same size, so the callees in these tests take and return one int.
+    fn stack_effect(instr: &Instr) -> i64 {
+        match instr {
+            Instr::Stc(constant) => constant.size().bytes() as i64,
+            Instr::Rda(size) => size.bytes() as i64,
+            Instr::RdaReg(Reg::Bl | Reg::Dl | Reg::Flg) => 1,
+            Instr::RdaReg(_) => 4,
+            Instr::Arith(_, size) | Instr::Pop(size) => -(size.bytes() as i64),
+            Instr::Cnv(from, to) => to.bytes() as i64 - from.bytes() as i64,
+            Instr::Inc(_, Reg::Sp, amount) => *amount as i64,
+            Instr::Dcr(_, Reg::Sp, amount) => -(*amount as i64),
+            _ => 0,
+        }
+    }
+
+    // Stack depth at every label relative to the start of its function, following the jumps to
+    // it. Panics when a label is reached with two different depths.
+    fn label_depths(il: &Il) -> FxHashMap<String, i64> {
+        let mut depths: FxHashMap<String, i64> = FxHashMap::default();
+        let reach = |depths: &mut FxHashMap<String, i64>, label: &str, depth: i64| {
+            let known = *depths.entry(label.to_string()).or_insert(depth);
+            assert_eq!(known, depth, "'{}' is reached with {} and {} bytes on the stack", label, known, depth);
+        };
+        // `None` after unconditional jumps, until the next label. Jump table entries are all
+        // reached with the depth of the `jmp &reg` before them.
+        let (mut depth, mut table) = (None, None);
+        for line in &il.lines {
+            match &line.instr {
+                // Labels nothing jumped to yet start a function.
+                Instr::Label(label) => {
+                    let entry = depth.or(table).or_else(|| depths.get(label).copied()).unwrap_or(0);
+                    reach(&mut depths, label, entry);
+                    depth = Some(entry);
+                }
+                Instr::Jmp(label) => {
+                    reach(&mut depths, label, depth.or(table).unwrap());
+                    depth = None;
+                }
+                Instr::JmpReg(_) => (depth, table) = (None, depth),
+                Instr::Cnd(label) => reach(&mut depths, label, depth.unwrap()),
+                Instr::Ret => depth = None,
+                instr => (depth, table) = (depth.map(|depth| depth + stack_effect(instr)), None),
+            }
         }
+        depths
+    }
+
+    fn depth_at(depths: &FxHashMap<String, i64>, prefix: &str) -> Vec<i64> {
+        let mut found: Vec<(usize, i64)> = depths
+            .iter()
+            .filter_map(|(label, depth)| Some((label.strip_prefix(prefix)?.parse().ok()?, *depth)))
+            .collect();
+        found.sort();
+        found.into_iter().map(|(_, depth)| depth).collect()
+    }
+
+    #[test]
+    fn test_loop_locals_are_reclaimed() {
+        let source = "
+            fn main() -> void {
+                let i = 0;
+                while (i < 10) { let a = i * 2; let b = a + 1; i = b; }
+                for (let j = 0; j < 3; j++) { let c = j; i += c; }
+            }";
+        let il = compile(source);
+        let depths = label_depths(&il);
+        // Only `i`, and `j` in the second loop, are on the stack whenever a loop starts over or ends.
+        assert_eq!(depth_at(&depths, "_while_start_"), [4, 8]);
+        assert_eq!(depth_at(&depths, "_while_continue_"), [4, 8]);
+        assert_eq!(depth_at(&depths, "_while_end_"), [4, 8]);
+
+        let slots: Vec<(&str, usize)> = il.frames[0].slots.iter().map(|slot| (slot.name.as_str(), slot.offset)).collect();
+        assert_eq!(slots, [("i", 0), ("a", 4), ("b", 8), ("j", 4), ("c", 8)]);
+    }
+
+    #[test]
+    fn test_jumps_out_of_nested_scopes_restore_the_stack() {
+        let source = "
+            fn f(n: int) -> int {
+                let total = 0;
+                while (total < n) {
+                    let step = 1;
+                    if (total > 5) { let skip = 2; total += skip; continue; }
+                    {
+                        let inner = step;
+                        if (inner > n) { let x = 0; x += 1; break; }
+                        if (inner == n) { let y = 3; return y; }
+                    }
+                    total += step;
+                }
+                return total;
+            }
+            fn main() -> void { let r = f(3); r += 1; }";
+        let depths = label_depths(&compile(source));
+        assert_eq!(depth_at(&depths, "_while_start_"), [4]);
+        assert_eq!(depth_at(&depths, "_while_continue_"), [4]);
+        assert_eq!(depth_at(&depths, "_while_end_"), [4]);
+        // Each `if` leaves the stack as it found it, whichever branch ran.
+        assert_eq!(depth_at(&depths, "_else_"), [8, 12, 12]);
+    }
+
+    #[test]
+    fn test_match_strategies() {
+        let matches = |cases: &str| format!("fn main() -> void {{ let s = 3; match (s) {{ {} else => s = 0; }} }}", cases);
+        let dense = matches("1 => s = 1; 2 => s = 2; 3 => s = 3; 4 => s = 4; 6 => s = 6;");
+        let sparse = matches("1 => s = 1; 20 => s = 2; 300 => s = 3; 4000 => s = 4;");
+        let jumps_through_table = |il: &Il| il.lines.iter().any(|line| matches!(line.instr, Instr::JmpReg(_)));
+
+        let options = CompileOptions::default().for_vm();
+        let il = lox::compile_il(&dense, &options).unwrap();
+        assert!(jumps_through_table(&il));
+        // `s` and the hidden copy of the subject, whichever case ran.
+        assert_eq!(depth_at(&label_depths(&il), "_match_end_"), [8]);
+        assert!(!jumps_through_table(&lox::compile_il(&sparse, &options).unwrap()));
+
+        // Text IL goes to JASM, which doesn't have to encode `jmp` like the built-in assembler.
+        let program = lox::compile_to_string(&dense, &CompileOptions::default()).unwrap();
+        assert!(!program.contains("_match_table_") && program.contains("case 6"), "{}", program);
+    }
+
+    // Folded conditions only keep the branch that runs, each in a scope of its own.
+    #[test]
+    fn test_constant_branches() {
+        let source = "fn main() -> void { if (1 > 2) print(\"a\"); else { let x = 1; print(\"b\"); } while (false) { print(\"c\"); } \
+            match (1 + 1) { 1 => print(\"d\"); 2, 3 => { let x = 2; print(\"e\"); } } match (0.1 + 0.2) { 0.3 => print(\"f\"); else => print(\"g\"); } }";
+        let program = lox::compile_to_string(source, &CompileOptions::default()).unwrap();
+        assert!(["_if_", "_else_", "_while_", "_match_"].iter().all(|label| !program.contains(label)), "{}", program);
+
+        let mut out = Vec::new();
+        lox::run_source(source, &CompileOptions::default().for_vm(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "b\ne\nf\n");
     }
 }
@@ -1,6 +1,6 @@
 use crate::lexer::token::{Hf64, Token, TokenType, Literal};
 use crate::lox::report;
-use crate::symbol::{Symbol, SymbolTable};
+use crate::symbol::SymbolTable;
 
 pub struct Scanner<'a> {
     source: &'a str,
@@ -9,6 +9,8 @@ pub struct Scanner<'a> {
     current: usize,
     line: usize,
     symbol_table: &'a mut SymbolTable,
+    // Untyped Lox only has `num`, integer literals are scanned as numbers too.
+    untyped: bool,
 }
 
 impl<'a> Scanner<'a> {
@@ -20,10 +22,16 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
-            symbol_table
+            symbol_table,
+            untyped: false,
         }
     }
 
+    pub fn untyped(mut self) -> Self {
+        self.untyped = true;
+        self
+    }
+
     pub fn scan_tokens(&mut self) {
         while !self.is_at_end() {
             self.start = self.current;
@@ -32,9 +40,10 @@ impl<'a> Scanner<'a> {
 
         let _ = &self.tokens.push(Token::new(
             TokenType::Eof,
-            Symbol(0),
+            self.symbol_table.intern(""),
             Literal::Void,
             self.line,
+            self.column(self.current),
         ));
     }
 
@@ -53,12 +62,46 @@ impl<'a> Scanner<'a> {
             '-' => {
                 let token_type = if self.match_operators('>') {
                     TokenType::Arrow
+                } else if self.match_operators('=') {
+                    TokenType::MinusEqual
+                } else if self.match_operators('-') {
+                    TokenType::MinusMinus
                 } else { TokenType::Minus };
                 self.add_token(token_type, Literal::Void)
             },
-            '+' => self.add_token(TokenType::Plus, Literal::Void),
+            '+' => {
+                let token_type = if self.match_operators('=') {
+                    TokenType::PlusEqual
+                } else if self.match_operators('+') {
+                    TokenType::PlusPlus
+                } else { TokenType::Plus };
+                self.add_token(token_type, Literal::Void)
+            },
             ';' => self.add_token(TokenType::Semicolon, Literal::Void),
-            '*' => self.add_token(TokenType::Star, Literal::Void),
+            '*' => {
+                let token_type = if self.match_operators('=') {
+                    TokenType::StarEqual
+                } else { TokenType::Star };
+                self.add_token(token_type, Literal::Void)
+            },
+            '%' => self.add_token(TokenType::Percent, Literal::Void),
+            '^' => self.add_token(TokenType::Caret, Literal::Void),
+            '&' => {
+                let token_type = if self.match_operators('&') {
+                    TokenType::And
+                } else {
+                    TokenType::Ampersand
+                };
+                self.add_token(token_type, Literal::Void);
+            }
+            '|' => {
+                let token_type = if self.match_operators('|') {
+                    TokenType::Or
+                } else {
+                    TokenType::Pipe
+                };
+                self.add_token(token_type, Literal::Void);
+            }
             ':' => self.add_token(TokenType::Colon, Literal::Void),
             '!' => {
                 let token_type = if self.match_operators('=') {
@@ -71,6 +114,8 @@ impl<'a> Scanner<'a> {
             '=' => {
                 let token_type = if self.match_operators('=') {
                     TokenType::EqualEqual
+                } else if self.match_operators('>') {
+                    TokenType::FatArrow
                 } else {
                     TokenType::Equal
                 };
@@ -79,6 +124,8 @@ impl<'a> Scanner<'a> {
             '<' => {
                 let token_type = if self.match_operators('=') {
                     TokenType::LessEqual
+                } else if self.match_operators('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
                 };
@@ -87,6 +134,8 @@ impl<'a> Scanner<'a> {
             '>' => {
                 let token_type = if self.match_operators('=') {
                     TokenType::GreaterEqual
+                } else if self.match_operators('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
                 };
@@ -100,6 +149,8 @@ impl<'a> Scanner<'a> {
                     }
                 } else if self.match_operators('*') {
                     self.block_comment();
+                } else if self.match_operators('=') {
+                    self.add_token(TokenType::SlashEqual, Literal::Void);
                 } else {
                     self.add_token(TokenType::Slash, Literal::Void);
                 }
@@ -109,7 +160,7 @@ impl<'a> Scanner<'a> {
             '"' => self.string(),
             '0'..='9' => self.number(),
             c => {
-                if c.is_alphabetic() {
+                if c.is_alphabetic() || c == '_' {
                     self.identifier();
                 } else {
                     report(
@@ -122,9 +173,12 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    // `current` is a byte offset, so multi-byte characters advance it by their encoded length.
     fn advance(&mut self) -> char {
-        let char = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let char = self.peek();
+        if !self.is_at_end() {
+            self.current += char.len_utf8();
+        }
         char
     }
 
@@ -132,26 +186,28 @@ impl<'a> Scanner<'a> {
         let lexeme_str = &self.source[self.start..self.current];
         let symbol = self.symbol_table.intern(lexeme_str);
         self.tokens
-            .push(Token::new(token_type, symbol, literal, self.line))
+            .push(Token::new(token_type, symbol, literal, self.line, self.column(self.start)))
+    }
+
+    fn column(&self, offset: usize) -> usize {
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        self.source[line_start..offset].chars().count() + 1
     }
 
     fn match_operators(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn block_comment(&mut self) {
@@ -214,42 +270,55 @@ impl<'a> Scanner<'a> {
             while self.peek().is_ascii_digit() {
                 self.advance();
             }
+        } else if !self.untyped {
+            // No fractional part, so it's an integer literal
+            let int_str = self.source[self.start..self.current].to_string();
+            // Out of range literals still become tokens, so parsing goes on without follow-up errors.
+            let int = int_str.parse::<i32>().unwrap_or_else(|_| {
+                report(self.line, &int_str, "Number literal out of range.");
+                0
+            });
+            self.add_token(TokenType::Number, Literal::Int(int));
+            return;
         }
 
+        // Digits always parse, but huge ones become infinity, which doesn't fit a literal.
         let num_str = self.source[self.start..self.current].to_string();
-        match num_str.parse::<f64>() {
-            Ok(num) => self.add_token(TokenType::Number, Literal::Num(Hf64::from(num))),
-            Err(e) => report(
-                self.line,
-                &num_str,
-                &format!("Failed to parse number: {}", e),
-            ),
-        }
+        let num = num_str.parse::<f64>().ok().and_then(|num| Hf64::try_from(num).ok()).unwrap_or_else(|| {
+            report(self.line, &num_str, "Number literal out of range.");
+            0.into()
+        });
+        self.add_token(TokenType::Number, Literal::Num(num));
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        };
-        self.source.chars().nth(self.current + 1).unwrap()
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn identifier(&mut self) {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
 
         let text = &self.source[self.start..self.current];
         let token_type: TokenType = match text {
-            "&&" => TokenType::And,
+            // Spellings of plain Lox, only keywords in untyped scripts.
+            "and" if self.untyped => TokenType::And,
+            "or" if self.untyped => TokenType::Or,
+            "fun" if self.untyped => TokenType::Fun,
+            "var" if self.untyped => TokenType::Var,
+            "nil" if self.untyped => TokenType::Void,
+            "break" => TokenType::Break,
             "class" => TokenType::Class,
+            "continue" => TokenType::Continue,
             "else" => TokenType::Else,
             "false" => TokenType::False,
             "for" => TokenType::For,
             "fn" => TokenType::Fun,
             "if" => TokenType::If,
+            "import" => TokenType::Import,
+            "match" => TokenType::Match,
             "void" => TokenType::Void,
-            "||" => TokenType::Or,
             "print" => TokenType::Print,
             "return" => TokenType::Return,
             "super" => TokenType::Super,
@@ -258,6 +327,7 @@ impl<'a> Scanner<'a> {
             "let" => TokenType::Var,
             "while" => TokenType::While,
             "num" => TokenType::Number,
+            "int" => TokenType::Int,
             "bool" => TokenType::Bool,
             "str" => TokenType::String,
             _ => TokenType::Identifier,
@@ -304,6 +374,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bitwise_and_logical_operators() {
+        let source = "% & && | || ^ << >> <= >=";
+        let mut symbol_table = SymbolTable::new();
+        let mut scanner = Scanner::new(source, &mut symbol_table);
+        scanner.scan_tokens();
+        let expected_types = [
+            TokenType::Percent,
+            TokenType::Ampersand,
+            TokenType::And,
+            TokenType::Pipe,
+            TokenType::Or,
+            TokenType::Caret,
+            TokenType::LessLess,
+            TokenType::GreaterGreater,
+            TokenType::LessEqual,
+            TokenType::GreaterEqual,
+            TokenType::Eof,
+        ];
+
+        assert_eq!(scanner.tokens.len(), expected_types.len());
+
+        for (token, expected_type) in scanner.tokens.iter().zip(expected_types.iter()) {
+            assert_eq!(token.token_type, *expected_type);
+        }
+    }
+
+    #[test]
+    fn test_compound_assignment_operators() {
+        let source = "+= -= *= /= ++ -- -> // comment";
+        let mut symbol_table = SymbolTable::new();
+        let mut scanner = Scanner::new(source, &mut symbol_table);
+        scanner.scan_tokens();
+        let expected_types = [
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::PlusPlus,
+            TokenType::MinusMinus,
+            TokenType::Arrow,
+            TokenType::Eof,
+        ];
+
+        assert_eq!(scanner.tokens.len(), expected_types.len());
+
+        for (token, expected_type) in scanner.tokens.iter().zip(expected_types.iter()) {
+            assert_eq!(token.token_type, *expected_type);
+        }
+    }
+
     #[test]
     fn test_string_literal() {
         let source = "\"hello world\"";
@@ -335,6 +456,23 @@ mod tests {
         assert_eq!(lexeme2, "456.789");
     }
 
+    #[test]
+    fn test_integer_and_float_literals() {
+        let source = "123 456.789 int num";
+        let mut symbol_table = SymbolTable::new();
+        let mut scanner = Scanner::new(source, &mut symbol_table);
+        scanner.scan_tokens();
+        assert_eq!(scanner.tokens.len(), 5); // Two number tokens + two type keywords + EOF
+
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[0].literal, Literal::Int(123));
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[1].literal, Literal::Num(Hf64::try_from(456.789).unwrap()));
+        assert_eq!(scanner.tokens[2].token_type, TokenType::Int);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[3].literal, Literal::Void);
+    }
+
     #[test]
     fn test_comments() {
         let source = "// This is a comment\n123";
@@ -394,7 +532,7 @@ mod tests {
     fn test_keywords() {
         let source = "class var if else";
         let mut symbol_table = SymbolTable::new();
-        let mut scanner = Scanner::new(source, &mut symbol_table);
+        let mut scanner = Scanner::new(source, &mut symbol_table).untyped();
         scanner.scan_tokens();
         let expected_types = [
             TokenType::Class,
@@ -410,6 +548,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_underscore_identifiers() {
+        let source = "_unused snake_case";
+        let mut symbol_table = SymbolTable::new();
+        let mut scanner = Scanner::new(source, &mut symbol_table);
+        scanner.scan_tokens();
+        let tokens = scanner.tokens;
+
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(symbol_table.resolve(tokens[0].lexeme), "_unused");
+        assert_eq!(symbol_table.resolve(tokens[1].lexeme), "snake_case");
+    }
+
+    #[test]
+    fn test_token_columns() {
+        let source = "let a = 1;\n  \"é\" b";
+        let mut symbol_table = SymbolTable::new();
+        let mut scanner = Scanner::new(source, &mut symbol_table);
+        scanner.scan_tokens();
+
+        let positions: Vec<(usize, usize)> = scanner.tokens.iter().map(|token| (token.line, token.column)).collect();
+        assert_eq!(positions, vec![(1, 1), (1, 5), (1, 7), (1, 9), (1, 10), (2, 3), (2, 7), (2, 8)]);
+    }
+
     #[test]
     fn test_identifiers() {
         let source = "foo bar baz";
@@ -447,7 +609,7 @@ mod tests {
     fn test_mixed_input() {
         let source = "var x = 100; // variable declaration\nfunc(y)";
         let mut symbol_table = SymbolTable::new();
-        let mut scanner = Scanner::new(source, &mut symbol_table);
+        let mut scanner = Scanner::new(source, &mut symbol_table).untyped();
         scanner.scan_tokens();
         let expected_types = [
             TokenType::Var,
@@ -467,4 +629,19 @@ mod tests {
             assert_eq!(token.token_type, *expected_type);
         }
     }
+
+    #[test]
+    fn test_plain_lox_spellings_are_identifiers_when_typed() {
+        let source = "and or fun var nil";
+        let mut symbol_table = SymbolTable::new();
+        let mut scanner = Scanner::new(source, &mut symbol_table);
+        scanner.scan_tokens();
+        let typed: Vec<TokenType> = scanner.tokens.iter().map(|token| token.token_type.clone()).collect();
+        assert_eq!(typed, [TokenType::Identifier, TokenType::Identifier, TokenType::Identifier, TokenType::Identifier, TokenType::Identifier, TokenType::Eof]);
+
+        let mut scanner = Scanner::new(source, &mut symbol_table).untyped();
+        scanner.scan_tokens();
+        let untyped: Vec<TokenType> = scanner.tokens.iter().map(|token| token.token_type.clone()).collect();
+        assert_eq!(untyped, [TokenType::And, TokenType::Or, TokenType::Fun, TokenType::Var, TokenType::Void, TokenType::Eof]);
+    }
 }
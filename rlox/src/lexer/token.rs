@@ -21,6 +21,8 @@ pub enum TokenType {
     Colon,
     Slash,
     Star,
+    Percent,
+    Caret,
 
     // One or two character tokens.
     Bang,
@@ -29,24 +31,40 @@ pub enum TokenType {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
     Arrow,
+    FatArrow,
+    Ampersand,
+    Pipe,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PlusPlus,
+    MinusMinus,
 
     // Literals & Types
     Identifier,
     String,
     Number,
+    Int,
     Bool,
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
+    Match,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
     Void,
     Or,
     Print,
@@ -59,35 +77,34 @@ pub enum TokenType {
     Eof,
 }
 
-impl From<TokenType> for Literal {
-    fn from(value: TokenType) -> Self {
-        match value {
-            TokenType::String => Self::Str("".into()),
-            TokenType::Number => Self::Num(0f64.into()),
-            TokenType::Bool => Self::False,
-            _ => unreachable!()
-        }
-    }
-}
-
 #[derive(Debug, Eq, Hash, PartialEq, Copy, Clone)]
 pub struct Hf64(pub Decimal);
 
-impl From<f64> for Hf64 {
-    fn from(value: f64) -> Self {
-        Hf64(Decimal::from_f64(value).unwrap())
+// Fails for infinities, NaN and magnitudes a decimal can't hold (past about 7.9e28).
+impl TryFrom<f64> for Hf64 {
+    type Error = ();
+
+    fn try_from(value: f64) -> Result<Self, ()> {
+        Decimal::from_f64(value).map(Hf64).ok_or(())
+    }
+}
+
+impl From<i32> for Hf64 {
+    fn from(value: i32) -> Self {
+        Hf64(Decimal::from(value))
     }
 }
 
+// Every decimal is in range of a double, `to_f64` never returns `None`.
 impl From<Hf64> for f64 {
     fn from(val: Hf64) -> Self {
-        val.0.to_f64().unwrap()
+        val.0.to_f64().unwrap_or_default()
     }
 }
 
 impl From<&Hf64> for f64 {
     fn from(val: &Hf64) -> Self {
-        val.0.to_f64().unwrap()
+        val.0.to_f64().unwrap_or_default()
     }
 }
 
@@ -101,9 +118,12 @@ impl fmt::Display for Hf64 {
 pub enum Literal {
     Str(String),
     Num(Hf64),
+    Int(i32),
     True,
     False,
     Void,
+    // Only used as a type placeholder for class-typed annotations.
+    Class(Symbol),
 }
 
 pub struct ErrorToken {
@@ -130,27 +150,18 @@ pub struct Token {
     pub lexeme: Symbol,
     pub literal: Literal,
     pub line: usize,
-}
-
-impl From<Token> for Literal {
-    fn from(value: Token) -> Self {
-        match value.token_type {
-            TokenType::String => Self::Str("".into()),
-            TokenType::Number => Self::Num(0f64.into()),
-            TokenType::Bool => Self::False,
-            TokenType::Void => Self::Void,
-            _ => unreachable!()
-        }
-    }
+    // 1-based, counted in characters from the start of the line.
+    pub column: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: Symbol, literal: Literal, line: usize) -> Self {
+    pub fn new(token_type: TokenType, lexeme: Symbol, literal: Literal, line: usize, column: usize) -> Self {
         Token {
             token_type,
             lexeme,
             literal,
             line,
+            column,
         }
     }
 }
@@ -160,9 +171,11 @@ impl fmt::Display for Token {
         let literal = match &self.literal {
             Literal::Str(str) => str.clone(),
             Literal::Num(num) => num.to_string(),
+            Literal::Int(int) => int.to_string(),
             Literal::Void => "".to_string(),
             Literal::True => "true".to_string(),
             Literal::False => "false".to_string(),
+            Literal::Class(name) => name.to_string(),
         };
         write!(
             f,
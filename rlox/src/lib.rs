@@ -1,13 +1,24 @@
+pub mod analysis;
+pub mod cli;
+pub mod difftest;
+pub mod assembler;
 pub mod environment;
+pub mod evaluator;
 pub mod expr;
+pub mod format;
 pub mod globals;
+pub mod il;
 pub mod interpreter;
 pub mod lexer;
 pub mod lox;
+pub mod module;
+pub mod optimizer;
 pub mod parser;
+pub mod repl;
 pub mod resolver;
 pub mod stmt;
 pub mod lox_value;
 pub mod lox_callable;
 pub mod symbol;
 pub mod scope;
+pub mod vm;
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::env;
 use std::process::Command;
 use std::io;
@@ -9,11 +10,29 @@ use std::io::{stdin, stdout, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 
 
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::analysis::control_flow::ControlFlow;
+use crate::analysis::definite_assignment::DefiniteAssignment;
+use crate::analysis::usage::Usage;
+use crate::analysis::{Warning, WarningFilter, WarningKind};
+use crate::assembler::{self, HEAP_SIZE, STACK_SIZE};
+use crate::difftest;
+use crate::format;
+use crate::il::Il;
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::expr::ExprPool;
+use crate::interpreter::Interpreter;
 use crate::lexer::scanner;
 use crate::lexer::token::{ErrorToken, TokenType};
+use crate::module::{self, Module, Program};
+use crate::optimizer::constant_folding::ConstantFolding;
+use crate::optimizer::peephole;
 use crate::parser::Parser;
+use crate::repl::Repl;
+use crate::resolver::Resolver;
+use crate::scope::{Scope, ScopeRef};
+use crate::stmt::Stmt;
 use crate::symbol::SymbolTable;
+use crate::vm::Vm;
 
 #[derive(Debug)]
 pub enum LoxError {
@@ -36,114 +55,402 @@ impl fmt::Display for LoxError {
 
 impl Error for LoxError {}
 
+impl LoxError {
+    // Exit codes from sysexits.h, the same ones the book's interpreters use.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::IOError(_) => 74,
+            LoxError::RuntimeError(_) => 70,
+            LoxError::Error(_) | LoxError::CompilationError(_) => 65,
+        }
+    }
+}
+
 impl From<io::Error> for LoxError {
     fn from(err: io::Error) -> LoxError {
         LoxError::IOError(err)
     }
 }
 
-static mut HAD_ERROR: bool = false;
-static mut HAD_RUNTIME_ERROR: bool = false;
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    pub warnings: WarningFilter,
+    // Runs the peephole optimizer over the generated IL (`-O`).
+    pub optimize: bool,
+    // Annotates the text IL with source lines, frame layouts and label references (`--annotate`).
+    pub annotate: bool,
+    // Directory `build` and `jasm` write into instead of next to the sources (`--out-dir`).
+    pub out_dir: Option<PathBuf>,
+    // Output file of a single source (`-o`).
+    pub out_file: Option<PathBuf>,
+    // Memory the program asks for, in bytes (`--stack-size`, `--heap-size`).
+    pub stack_size: u32,
+    pub heap_size: u32,
+    // Debug info for the built files (`-g`).
+    pub debug_info: bool,
+    // The JASM tools, found next to the executable unless given (`--jasm-path`, `--csr-path`).
+    pub jasm_path: Option<PathBuf>,
+    pub csr_path: Option<PathBuf>,
+    // Dense matches become jump tables, whose entries rely on the built-in assembler's encoding.
+    // Only IL for the embedded VM has them, JASM gets compare chains.
+    pub jump_tables: bool,
+}
 
-fn check_errors() -> Result<(), LoxError> {
-    unsafe {
-        if HAD_ERROR {
-            return Err(LoxError::Error("Compilation error".to_string()));
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            warnings: WarningFilter::default(),
+            optimize: false,
+            annotate: false,
+            out_dir: None,
+            out_file: None,
+            stack_size: STACK_SIZE,
+            heap_size: HEAP_SIZE,
+            debug_info: false,
+            jasm_path: None,
+            csr_path: None,
+            jump_tables: false,
         }
-        if HAD_RUNTIME_ERROR {
-            return Err(LoxError::RuntimeError("Runtime error".to_string()));
+    }
+}
+
+impl CompileOptions {
+    // The same options for IL that the built-in assembler turns into bytecode for the embedded VM.
+    pub fn for_vm(&self) -> Self {
+        CompileOptions { jump_tables: true, ..self.clone() }
+    }
+}
+
+// What a compilation reported, collected by `compile_to` instead of printed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    pub errors: Vec<String>,
+    pub warnings: Vec<Warning>,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let warnings = self.warnings.iter().map(Warning::to_string);
+        let lines: Vec<String> = warnings.chain(self.errors.iter().cloned()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Error for Diagnostics {}
+
+// Thread local so that compilations running side by side (e.g. tests) don't see each other's errors.
+thread_local! {
+    static HAD_ERROR: Cell<bool> = const { Cell::new(false) };
+    static HAD_RUNTIME_ERROR: Cell<bool> = const { Cell::new(false) };
+    static DIAGNOSTICS: RefCell<Option<Diagnostics>> = const { RefCell::new(None) };
+}
+
+// Hands a diagnostic to `compile_to` if it is collecting them, false if it has to be printed.
+fn collected(push: impl FnOnce(&mut Diagnostics)) -> bool {
+    DIAGNOSTICS.with_borrow_mut(|diagnostics| diagnostics.as_mut().map(push).is_some())
+}
+
+pub(crate) fn reset_errors() {
+    HAD_ERROR.set(false);
+}
+
+pub(crate) fn check_errors() -> Result<(), LoxError> {
+    if HAD_ERROR.get() {
+        return Err(LoxError::Error("Compilation error".to_string()));
+    }
+    if HAD_RUNTIME_ERROR.get() {
+        return Err(LoxError::RuntimeError("Runtime error".to_string()));
+    }
+    Ok(())
+}
+
+// Path given with `-o`, otherwise a file named after `source` with the given extension, in the
+// `--out-dir` or next to the source.
+fn output_path(source: &str, extension: &str, options: &CompileOptions) -> Result<String, LoxError> {
+    let source_path = Path::new(source);
+    let stem = source_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| LoxError::Error(format!("Invalid source file name '{}'.", source)))?;
+    let dest_path = match (&options.out_file, &options.out_dir) {
+        (Some(out_file), _) => out_file.clone(),
+        (None, Some(out_dir)) => out_dir.join(format!("{}.{}", stem, extension)),
+        (None, None) => source_path.parent().unwrap_or(Path::new("")).join(format!("{}.{}", stem, extension)),
+    };
+
+    dest_path
+        .into_os_string()
+        .into_string()
+        .map_err(|path| LoxError::Error(format!("Output path '{}' is not valid UTF-8.", path.to_string_lossy())))
+}
+
+fn prepare_outputs(files: &[&str], options: &CompileOptions) -> Result<(), LoxError> {
+    if options.out_file.is_some() && files.len() > 1 {
+        return Err(LoxError::Error("'-o' takes a single source file, use '--out-dir' for more.".into()));
+    }
+    if let Some(out_dir) = &options.out_dir {
+        fs::create_dir_all(out_dir)?;
+    }
+    Ok(())
+}
+
+// The JASM tools are shipped next to the rlox-jasm executable.
+fn tool_path(name: &str, given: &Option<PathBuf>) -> Result<PathBuf, LoxError> {
+    if let Some(path) = given {
+        return Ok(path.clone());
+    }
+    let exe = env::current_exe()?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| LoxError::Error(format!("Couldn't locate '{}' next to '{}'.", name, exe.display())))?;
+    Ok(dir.join(name))
+}
+
+// Names the tool, a bare "No such file or directory" doesn't say what is missing.
+fn tool_error(tool: &Path, err: io::Error) -> LoxError {
+    LoxError::IOError(io::Error::new(err.kind(), format!("Couldn't run '{}': {}. Build the JASM tools with `make tools`.", tool.display(), err)))
+}
+
+pub fn run_files(files: &[&str], options: &CompileOptions) -> Result<(), LoxError> {
+    for source in files {
+        run_file(Path::new(source), options, &mut stdout().lock())?;
+    }
+
+    Ok(())
+}
+
+pub fn run_file(path: &Path, options: &CompileOptions, out: &mut impl Write) -> Result<(), LoxError> {
+    let src = std::fs::read_to_string(path)?;
+    run_il(&compile_file_il(&src, path, &options.for_vm())?, options, out)
+}
+
+// Compiles and assembles in memory, then executes on the embedded VM.
+pub fn run_source(source: &str, options: &CompileOptions, out: &mut impl Write) -> Result<(), LoxError> {
+    run_il(&compile_il(source, &options.for_vm())?, options, out)
+}
+
+fn run_il(il: &Il, options: &CompileOptions, out: &mut impl Write) -> Result<(), LoxError> {
+    let mut program = Vec::new();
+    let debug_map = assembler::assemble_entry(il, "main", options.stack_size, options.heap_size, &mut program)?;
+    Vm::new(&program)?.with_debug_map(&debug_map).run(out)
+}
+
+pub fn interpret_files(files: &[&str]) -> Result<(), LoxError> {
+    for source in files {
+        let src = std::fs::read_to_string(source)?;
+        interpret_source(&src, &mut stdout().lock())?;
+    }
+
+    Ok(())
+}
+
+// Runs a script on the tree-walking evaluator. Annotations are optional and nothing is type checked.
+// Like in plain Lox every number is a double.
+pub fn interpret_source(source: &str, out: &mut impl Write) -> Result<(), LoxError> {
+    interpret(source, true, out)
+}
+
+// Runs a typed program on the tree-walking evaluator, with ints like the VM has them.
+pub fn interpret_typed_source(source: &str, out: &mut impl Write) -> Result<(), LoxError> {
+    interpret(source, false, out)
+}
+
+fn interpret(source: &str, untyped: bool, out: &mut impl Write) -> Result<(), LoxError> {
+    HAD_ERROR.set(false);
+    let mut symbol_table = SymbolTable::new();
+    let lexer_tokens = {
+        let mut lexer = scanner::Scanner::new(source, &mut symbol_table);
+        if untyped {
+            lexer = lexer.untyped();
         }
+        lexer.scan_tokens();
+
+        lexer.tokens
+    };
+    let mut parser = Parser::new(&symbol_table, lexer_tokens);
+    if untyped {
+        parser = parser.untyped();
     }
+    let (statements, expr_pool) = parser
+        .parse()
+        .map_err(|_| LoxError::Error("Error during parsing".into()))?;
+    check_errors()?;
+
+    let locals = Resolver::new(&expr_pool, &mut symbol_table).resolve_lox(&statements);
+    check_errors()?;
+
+    let mut evaluator = Evaluator::new(&expr_pool, &mut symbol_table, locals, out);
+    if untyped {
+        evaluator = evaluator.untyped();
+    }
+    evaluator.interpret(&statements)?;
     Ok(())
 }
 
-pub fn run_files(files: &[&str]) -> Result<(), LoxError> {
-    let byte_files = build_files(files)?;
+// Prints every program whose interpreted or compiled run diverges from its expectations.
+pub fn difftest_dirs(dirs: &[&str]) -> Result<(), LoxError> {
+    let (mut passed, mut failed) = (0, 0);
+    for dir in dirs {
+        let report = difftest::run_dir(Path::new(dir))?;
+        for mismatch in &report.mismatches {
+            println!("{}", mismatch);
+        }
+        passed += report.passed;
+        failed += report.mismatches.len();
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        return Err(LoxError::Error(format!("{} of {} programs diverged", failed, passed + failed)));
+    }
+    Ok(())
+}
+
+pub fn run_files_csr(files: &[&str], options: &CompileOptions) -> Result<(), LoxError> {
+    let byte_files = build_files(files, options)?;
 
     // invoke CSR to run byte_files
-    let status = Command::new(env::current_exe()?.parent().unwrap().join("csr"))
+    let csr = tool_path("csr", &options.csr_path)?;
+    let status = Command::new(&csr)
         .arg("-e").args(byte_files)
-        .status()?;
+        .status()
+        .map_err(|err| tool_error(&csr, err))?;
     
     if !status.success() {
-        Err(LoxError::CompilationError(format!("Failed to invoke csr [{}]", status.to_string())))
+        Err(LoxError::RuntimeError(format!("Failed to invoke csr [{}]", status)))
     }
     else {
         Ok(())
     }
 }
 
-pub fn build_files(files: &[&str]) -> Result<Vec<String>, LoxError> {
+// The `.jef` files are assembled by JASM from the text IL, written next to them. The built-in
+// assembler's bytecode is only understood by the embedded VM, CSR can't load it.
+pub fn build_files(files: &[&str], options: &CompileOptions) -> Result<Vec<String>, LoxError> {
+    prepare_outputs(files, options)?;
+    if options.debug_info {
+        return Err(LoxError::Error("'-g' needs the code addresses of JASM's '.jef' layout, which rlox-jasm doesn't know yet.".into()));
+    }
+    let jasm = tool_path("jasm", &options.jasm_path)?;
     let mut res: Vec<String> = Vec::new();
-    let il_files = jasm_files(files)?;
-    for il_file in il_files {
-        let source_path = Path::new(&il_file); 
-        let mut dest_path: PathBuf = source_path.parent().unwrap().to_path_buf();
-        dest_path.push(format!(
-            "{}.jef",
-            source_path.file_stem().unwrap().to_str().unwrap()
-        ));
-        
-        let status = Command::new(env::current_exe()?.parent().unwrap().join("jasm"))
-            .args(["-s", "-I", &il_file, "-o", dest_path.as_path().to_str().unwrap()])
-            .status()?;
 
+    for source in files {
+        let dest_path = output_path(source, "jef", options)?;
+
+        let src = std::fs::read_to_string(source)?;
+        let il_file = Path::new(&dest_path).with_extension("jasm");
+        write_program(&src, Path::new(source), &mut File::create(&il_file)?, options)?;
+        let status = Command::new(&jasm)
+            .arg("-s").arg("-I").arg(&il_file).arg("-o").arg(&dest_path)
+            .status()
+            .map_err(|err| tool_error(&jasm, err))?;
         if !status.success() {
-            return Err(LoxError::CompilationError(format!("Failed to invoke jasm [{}]", status.to_string())));
+            return Err(LoxError::CompilationError(format!("Failed to invoke jasm [{}]", status)));
         }
-
-        res.push(dest_path.into_os_string().into_string().unwrap());
+        res.push(dest_path);
     }
 
     Ok(res)
 }
 
-pub fn jasm_files(files: &[&str]) -> Result<Vec<String>, LoxError> {
+pub fn jasm_files(files: &[&str], options: &CompileOptions) -> Result<Vec<String>, LoxError> {
+    prepare_outputs(files, options)?;
     let mut res: Vec<String> = Vec::new();
 
     for source in files {
-        let source_path = Path::new(source); 
-        let mut dest_path: PathBuf = source_path.parent().unwrap().to_path_buf();
-        dest_path.push(format!(
-            "{}.jasm",
-            source_path.file_stem().unwrap().to_str().unwrap()
-        ));
+        let dest_path = output_path(source, "jasm", options)?;
 
+        let src = std::fs::read_to_string(source)?;
         let mut output = File::create(&dest_path)?;
-        write!(output,
+        write_program(&src, Path::new(source), &mut output, options)?;
+        res.push(dest_path);
+    }
+
+    Ok(res)
+}
+
+// A complete `.jasm` program: the prologue with the entry calling `main`, then the compiled source.
+pub fn write_program(source: &str, path: &Path, output: &mut impl Write, options: &CompileOptions) -> Result<(), LoxError> {
+    write!(output,
 "This file has been generated automatically by rlox-jasm.
 rlox, Rust implementation of lox from Crafting Interpreters by Emirhan TALA.
 rlox-jasm, JASM IL and Bytecode generation for rlox by Yusuf Ender Osmanoğlu.
 
 .prep
     org __jasm_IL_entry_main__
-    sts 1032
-    sth 1024
+    sts {}
+    sth {}
 .body
     __jasm_IL_entry_main__:
         cal main
         jmp __jasm_IL_end__
-")?;
+", options.stack_size, options.heap_size)?;
 
-        // turn AST into bytecode
-        let src = std::fs::read_to_string(source)?;
-        let result = compile(&src, &mut output);
+    // turn AST into bytecode
+    compile_file(source, path, output, options)?;
+
+    write!(output, "\n__jasm_IL_end__:\n.end\n\nEnd of generated IL.")?;
+    Ok(())
+}
 
-        if result.is_err() {
-            return Err(result.unwrap_err());
+// Compiles into a complete `.jasm` program written to `out`, with the errors and warnings collected
+// instead of printed. Imports are relative to the working directory. After errors `out` may hold
+// the start of the program.
+pub fn compile_to(source: &str, options: &CompileOptions, out: &mut impl Write) -> Result<(), Diagnostics> {
+    DIAGNOSTICS.set(Some(Diagnostics::default()));
+    let result = write_program(source, Path::new(""), out, options);
+    let mut diagnostics = DIAGNOSTICS.take().unwrap_or_default();
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            // Parse errors were reported one by one, the error ending the compilation only sums them up.
+            if diagnostics.errors.is_empty() {
+                diagnostics.errors.push(err.to_string());
+            }
+            Err(diagnostics)
         }
+    }
+}
+
+// Same as `compile_to`, into a string.
+pub fn compile_to_string(source: &str, options: &CompileOptions) -> Result<String, Diagnostics> {
+    let mut out = Vec::new();
+    compile_to(source, options, &mut out)?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
 
-        write!(output, "\n__jasm_IL_end__:\n.end\n\nEnd of generated IL.")?;
-        res.push(dest_path.into_os_string().into_string().unwrap());
+// Compiles without writing anything, only errors and warnings are reported.
+pub fn check_files(files: &[&str], options: &CompileOptions) -> Result<(), LoxError> {
+    for source in files {
+        let src = std::fs::read_to_string(source)?;
+        compile_file_il(&src, Path::new(source), options)?;
     }
 
-    Ok(res)
+    Ok(())
 }
 
-pub fn run_prompt() -> Result<(), LoxError> {
+// Formats the files in place, or only lists the ones that aren't formatted with `check`.
+pub fn format_files(files: &[&str], check: bool) -> Result<Vec<String>, LoxError> {
+    let mut unformatted = Vec::new();
+    for source in files {
+        let src = std::fs::read_to_string(source)?;
+        let formatted = format::format_source(&src);
+        if formatted != src {
+            if !check {
+                fs::write(source, &formatted)?;
+            }
+            unformatted.push(source.to_string());
+        }
+    }
+
+    Ok(unformatted)
+}
+
+pub fn run_prompt(options: &CompileOptions) -> Result<(), LoxError> {
     let stdin = stdin();
     let input = stdin.lock();
     let mut reader = BufReader::new(input);
+    let mut repl = Repl::new(options);
 
     loop {
         print!("> ");
@@ -156,35 +463,84 @@ pub fn run_prompt() -> Result<(), LoxError> {
             break; // EOF reached
         }
 
-        //run(line.trim())?;
-
-        unsafe {
-            HAD_ERROR = false;
+        // A bad line is reported, the session goes on.
+        if let Err(err) = repl.eval(&line, &mut stdout().lock()) {
+            eprintln!("{}", err);
         }
     }
 
     Ok(())
 }
 
-pub fn compile(source: &str, out: &mut File) -> Result<(), LoxError> {
-    let mut symbol_table = SymbolTable::new(); // For the lexer.
-    let lexer_tokens = {
-        let mut lexer = scanner::Scanner::new(source, &mut symbol_table);
-        lexer.scan_tokens();
+pub fn compile(source: &str, out: &mut impl Write, options: &CompileOptions) -> Result<(), LoxError> {
+    compile_file(source, Path::new(""), out, options)
+}
 
-        lexer.tokens
-    };
-    let parser = Parser::new(&symbol_table, lexer_tokens);
-    let (statements, expr_pool) = parser
-        .parse()
-        .map_err(|_| LoxError::Error("Error during parsing".into()))?;
-    check_errors()?;
+// Same as `compile`, with the imports of `source` resolved relative to `path`.
+pub fn compile_file(source: &str, path: &Path, out: &mut impl Write, options: &CompileOptions) -> Result<(), LoxError> {
+    let il = compile_file_il(source, path, options)?;
+    if options.annotate {
+        il.write_annotated(out, source)?;
+    } else {
+        il.write_to(out)?;
+    }
+    Ok(())
+}
 
-    //let locals = Resolver::new(&expr_pool, &mut symbol_table).resolve_lox(&statements);
+pub fn compile_il(source: &str, options: &CompileOptions) -> Result<Il, LoxError> {
+    compile_file_il(source, Path::new(""), options)
+}
 
-    let mut interpreter = Interpreter::new(&expr_pool, &mut symbol_table);
-    interpreter.gen_il(&statements, out, None)?;
-    Ok(())
+// Compiles `source` linked with the modules it imports, `path` is the file it was read from.
+pub fn compile_file_il(source: &str, path: &Path, options: &CompileOptions) -> Result<Il, LoxError> {
+    // Errors of a previous file don't carry over into this one.
+    HAD_ERROR.set(false);
+    let Program { modules, mut expr_pool, mut symbol_table } = module::link(source, path)?;
+    let (il, _) = gen_modules(&modules, &mut expr_pool, &mut symbol_table, options, None, &mut 0)?;
+    Ok(il)
+}
+
+// Everything after parsing, for files and REPL lines alike: the analyses and their warnings,
+// folding, codegen (which is also the type check) and the peephole optimizer. Codegen continues in a top level scope
+// under `parent` and numbers its labels from `labels` on, the scope is returned with the IL.
+pub(crate) fn gen_modules(
+    modules: &[Module],
+    expr_pool: &mut ExprPool,
+    symbol_table: &mut SymbolTable,
+    options: &CompileOptions,
+    parent: Option<ScopeRef>,
+    labels: &mut usize,
+) -> Result<(Il, ScopeRef), LoxError> {
+    let statements: Vec<Stmt> = modules.iter().flat_map(|module| module.statements.iter().cloned()).collect();
+
+    DefiniteAssignment::new(expr_pool, symbol_table).check(&statements)?;
+    let mut found = Vec::new();
+    for module in modules {
+        let mut warnings = ControlFlow::new(expr_pool, symbol_table).check(&module.statements)?;
+        warnings.extend(Usage::new(expr_pool, symbol_table).check(&module.statements));
+        // Modules are libraries, they don't call all of their own functions.
+        found.extend(warnings.into_iter().filter(|warning| {
+            (module.name.is_none() || warning.kind != WarningKind::UnusedFunction)
+                && options.warnings.is_enabled(warning.kind)
+                && module.allowed.is_enabled(warning.kind)
+        }));
+    }
+    found.sort_by_key(|warning| warning.line);
+    for warning in &found {
+        self::warning(warning);
+    }
+
+    let statements = ConstantFolding::new(expr_pool).fold(statements);
+
+    let scope = Rc::new(RefCell::new(Scope::new(parent, Some(1), None)));
+    let mut interpreter = Interpreter::new(expr_pool, symbol_table).with_label_counter(*labels).with_jump_tables(options.jump_tables);
+    let mut il = Il::new();
+    interpreter.gen_il(&statements, &mut il, Some(scope.clone()))?;
+    *labels = interpreter.label_counter();
+    if options.optimize {
+        peephole::optimize(&mut il);
+    }
+    Ok((il, scope))
 }
 
 pub fn error(token: &ErrorToken, message: &str) {
@@ -196,18 +552,166 @@ pub fn error(token: &ErrorToken, message: &str) {
 }
 
 pub fn report(line_num: usize, line: &str, message: &str) {
-    eprintln!("[line {line_num}] {:?}: {message}", line);
+    let text = format!("[line {line_num}] {:?}: {message}", line);
+    if !collected(|diagnostics| diagnostics.errors.push(text.clone())) {
+        eprintln!("{}", text);
+    }
+    HAD_ERROR.set(true);
+}
 
-    unsafe {
-        HAD_ERROR = true;
+pub fn warning(warning: &Warning) {
+    if !collected(|diagnostics| diagnostics.warnings.push(warning.clone())) {
+        eprintln!("{}", warning);
     }
 }
 
 pub fn runtime_error(error: RuntimeError) {
     let (token, err_msg) = error.get_info();
     eprintln!("{}\n[line {}]", err_msg, token.line);
+    HAD_RUNTIME_ERROR.set(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small xorshift generator so the fuzz cases are reproducible without extra dependencies.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.next() % items.len()]
+        }
+    }
+
+    const VOCABULARY: &[&str] = &[
+        "fn", "let", "if", "else", "while", "for", "match", "break", "continue", "return", "print",
+        "class", "this", "super", "true", "false", "nil", "and", "or", "void", "int", "num", "str", "bool",
+        "main", "a", "b", "f", "Point", "0", "1", "42", "3.5", "2147483648", "99999999999999999999",
+        "100000000000000000000000000000000000000000.0", "1e400", "0.0000000000000000000000000000001",
+        "\"hi\"", "\"\"", "\"open",
+        "(", ")", "{", "}", "[", "]", ",", ".", ";", ":", "->", "=>", "=", "==", "!=", "!", "<", "<=", ">", ">=",
+        "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "&&", "||", "+=", "-=", "*=", "/=", "++", "--",
+        "@", "é", "//", "/*", "*/", "\n",
+    ];
+
+    const SEEDS: &[&str] = &[
+        "fn main() -> void { let a: int = 1; a += 2; print(\"x\"); }",
+        "fn f(a: int, b: num) -> int { if (a < 2) { return a; } return f(a - 1, b) + 1; } fn main() -> void { let c = f(3, 1.5); }",
+        "fn main() -> void { let i: int; i = 0; while (i < 10) { if (i == 3) { break; } i++; } for (let j = 0; j < 3; j++) { continue; } }",
+        "fn main() -> void { let x = 2; match (x) { 0 => print(\"a\"); 1, 2 => { let y = -x; } else => { } } }",
+        "fn main() -> void { let s = \"a\"; match (s) { \"a\" => print(s); else => print(\"b\"); } let n: num = num(3) / 2.0; }",
+        "class Point { init() { this.x = 1; } } fn main() -> void { let p: Point; p.x = super.y; }",
+    ];
+
+    fn compile_str(source: &str) -> Result<(), LoxError> {
+        compile(source, &mut Vec::new(), &CompileOptions::default())
+    }
+
+    #[test]
+    fn test_random_token_streams_dont_panic() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..2000 {
+            let len = rng.next() % 40;
+            let source: Vec<&str> = (0..len).map(|_| rng.pick(VOCABULARY)).collect();
+            let source = source.join(" ");
+            let _ = compile_str(&source);
+        }
+    }
+
+    #[test]
+    fn test_number_literals_out_of_range() {
+        let source = "fn main() -> void {\n    let a = 100000000000000000000000000000000000000000.0;\n    let b = 2147483648;\n}";
+        let diagnostics = compile_to_string(source, &CompileOptions::default()).unwrap_err();
+        assert_eq!(diagnostics.errors, [
+            "[line 2] \"100000000000000000000000000000000000000000.0\": Number literal out of range.",
+            "[line 3] \"2147483648\": Number literal out of range.",
+        ]);
+    }
+
+    #[test]
+    fn test_mutated_programs_dont_panic() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for seed in SEEDS {
+            assert!(compile_str(seed).is_ok() || seed.starts_with("class"), "seed program failed: {}", seed);
+            let tokens: Vec<&str> = seed.split(' ').collect();
+            for _ in 0..500 {
+                let mut mutated = tokens.clone();
+                for _ in 0..1 + rng.next() % 3 {
+                    let idx = rng.next() % mutated.len();
+                    match rng.next() % 3 {
+                        0 => { mutated.remove(idx); },
+                        1 => mutated.insert(idx, rng.pick(VOCABULARY)),
+                        _ => mutated[idx] = rng.pick(VOCABULARY),
+                    }
+                    if mutated.is_empty() { break; }
+                }
+                let _ = compile_str(&mutated.join(" "));
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_to_string() {
+        let program = compile_to_string("fn main() -> void { print(\"hi\"); }", &CompileOptions::default()).unwrap();
+        assert!(program.contains("cal main") && program.contains("\tmain:"), "{}", program);
+
+        let source = "fn main() -> void { let a = 1; }\nfn f() -> int { return \"no\"; }";
+        let diagnostics = compile_to_string(source, &CompileOptions::default()).unwrap_err();
+        assert_eq!(diagnostics.warnings.iter().map(|warning| warning.kind).collect::<Vec<_>>(), [WarningKind::UnusedVariable, WarningKind::UnusedFunction]);
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert!(diagnostics.errors[0].contains("[line 2] Return type doesn't match"));
+
+        let diagnostics = compile_to_string("fn main() -> void { let = 1; }", &CompileOptions::default()).unwrap_err();
+        assert_eq!(diagnostics.errors, ["[line 1] \"at '='\": Expect variable name."]);
+
+        let mut file = io::Cursor::new(Vec::new());
+        compile_to("fn main() -> void { print(\"hi\"); }", &CompileOptions::default(), &mut file).unwrap();
+        assert_eq!(String::from_utf8(file.into_inner()).unwrap(), program);
+    }
+
+    #[test]
+    fn test_unknown_types() {
+        let options = CompileOptions::default();
+        let diagnostics = compile_to_string("fn main() -> void { }\nfn f() -> nmu { return 1; }", &options).unwrap_err();
+        assert_eq!(diagnostics.errors, ["[line 2] \"at 'nmu'\": Unknown type 'nmu'."]);
+        let diagnostics = compile_to_string("fn main() -> void { let b: strr = \"x\"; }", &options).unwrap_err();
+        assert_eq!(diagnostics.errors, ["[line 1] \"at 'strr'\": Unknown type 'strr'."]);
+
+        let diagnostics = compile_to_string("fn main() -> void { let p: Point = 1; }\nclass Point { }", &options).unwrap_err();
+        assert!(diagnostics.errors[0].contains("declared as 'Point' but initialized with 'int'"), "{}", diagnostics);
+    }
+
+    #[test]
+    fn test_dead_branches_are_type_checked() {
+        for (body, error) in [
+            ("if (false) { let a: int = \"oops\"; }", "declared as 'int' but initialized with 'str'"),
+            ("if (1 > 2) { } else { undefined_fn(1, 2); }", "Couldn't find 'undefined_fn'"),
+            ("while (false) { let b: bool = 3; }", "declared as 'bool' but initialized with 'int'"),
+            ("match (1) { 1 => print(\"one\"); \"x\" => print(\"x\"); }", "Match pattern of type 'str'"),
+            ("match (1) { 1 => print(\"one\"); 1 => print(\"dup\"); }", "Duplicate pattern"),
+        ] {
+            let source = format!("fn main() -> void {{ {} }}", body);
+            let diagnostics = compile_to_string(&source, &CompileOptions::default()).unwrap_err();
+            assert!(diagnostics.errors.iter().any(|err| err.contains(error)), "{}: {}", body, diagnostics);
+        }
+    }
 
-    unsafe {
-        HAD_RUNTIME_ERROR = true;
+    #[test]
+    fn test_output_paths() {
+        let mut options = CompileOptions::default();
+        assert_eq!(output_path("src/a.lox", "jasm", &options).unwrap(), "src/a.jasm");
+        options.out_dir = Some("out".into());
+        assert_eq!(output_path("src/a.lox", "jef", &options).unwrap(), "out/a.jef");
+        options.out_file = Some("b.jef".into());
+        assert_eq!(output_path("src/a.lox", "jef", &options).unwrap(), "b.jef");
+        assert!(build_files(&["a.lox", "b.lox"], &options).is_err());
     }
 }
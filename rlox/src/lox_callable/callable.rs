@@ -1,6 +1,6 @@
 use core::fmt;
 
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::evaluator::{Evaluator, RuntimeError};
 use crate::lox_value::LoxValue;
 use crate::symbol::SymbolTable;
 
@@ -8,7 +8,7 @@ pub trait Callable {
     fn arity(&self, symbol_table: &mut SymbolTable) -> usize;
     fn call(
         &self,
-        interpreter: &mut Interpreter,
+        evaluator: &mut Evaluator,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError>;
     fn get_name(&self) -> String;
@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use rustc_hash::FxHashMap;
 use std::rc::Rc;
 
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::evaluator::{Evaluator, RuntimeError};
 use crate::lox_callable::callable::Callable;
 use crate::lox_callable::lox_instance::LoxInstance;
 use crate::lox_value::{LoxCallable, LoxValue};
@@ -43,14 +43,14 @@ impl Callable for LoxClass {
 
     fn call(
         &self,
-        interpreter: &mut Interpreter,
+        evaluator: &mut Evaluator,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError> {
         let instance = Rc::new(RefCell::new(LoxInstance::new(self)));
 
-        let initializer_option = self.find_method(&interpreter.symbol_table.intern("init"));
+        let initializer_option = self.find_method(&evaluator.symbol_table.intern("init"));
         if let Some(LoxCallable::Function(initializer_function)) = initializer_option {
-            initializer_function.bind(&instance, interpreter.symbol_table).call(interpreter, arguments)?;
+            initializer_function.bind(&instance, evaluator.symbol_table).call(evaluator, arguments)?;
         }
 
         Ok(LoxValue::Callable(LoxCallable::Instance(instance)))
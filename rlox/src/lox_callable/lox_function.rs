@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::environment::Environment;
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::evaluator::{Evaluator, RuntimeError};
 use crate::lox_value::{LoxCallable, LoxValue};
 use crate::lexer::token::Token;
 use crate::lox_callable::callable::Callable;
@@ -47,7 +47,7 @@ impl Callable for LoxFunction {
 
     fn call(
         &self,
-        interpreter: &mut Interpreter,
+        evaluator: &mut Evaluator,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError> {
         let environment = Environment::with_enclosing(Rc::clone(&self.closure));
@@ -58,12 +58,11 @@ impl Callable for LoxFunction {
         }
 
         // Execute the function body
-        unreachable!();
-        /*match interpreter.evaluate_block_stmt(&self.body, Some(environment)) {
-            Err(RuntimeError::Return(value)) => self.handle_return(value, interpreter.symbol_table),
+        match evaluator.execute_block(&self.body, environment) {
+            Err(RuntimeError::Return(value)) => self.handle_return(value, evaluator.symbol_table),
             Err(err) => Err(err), // Propagate other errors
-            Ok(_) => self.handle_return(LoxValue::Void, interpreter.symbol_table),
-        }*/
+            Ok(_) => self.handle_return(LoxValue::Void, evaluator.symbol_table),
+        }
     }
 
 
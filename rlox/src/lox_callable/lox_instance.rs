@@ -3,7 +3,7 @@ use std::rc::Rc;
 
 use rustc_hash::FxHashMap;
 
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::evaluator::{Evaluator, RuntimeError};
 use crate::lox_value::{LoxCallable, LoxValue};
 use crate::lexer::token::Token;
 use crate::lox_callable::callable::Callable;
@@ -39,7 +39,7 @@ impl LoxInstance {
 
         Err(RuntimeError::InstanceError(
             name.clone(),
-            format!("Undefined property '{}'.", name.lexeme),
+            format!("Undefined property '{}'.", symbol_table.resolve(name.lexeme)),
         ))
     }
 
@@ -55,7 +55,7 @@ impl Callable for LoxInstance {
 
     fn call(
         &self,
-        _interpreter: &mut Interpreter,
+        _evaluator: &mut Evaluator,
         _arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError> {
         todo!()
@@ -3,7 +3,7 @@ use std::fmt;
 use std::rc::Rc;
 
 use crate::globals::{ClockFunction, InputFunction};
-use crate::interpreter::{Interpreter, RuntimeError};
+use crate::evaluator::{Evaluator, RuntimeError};
 use crate::lexer::token::{Hf64, Literal, Token, TokenType};
 use crate::lox_callable::lox_class::LoxClass;
 use crate::lox_callable::lox_function::LoxFunction;
@@ -46,8 +46,10 @@ pub enum LoxValue {
     Void,
     Boolean(bool),
     Number(f64),
+    Integer(i32),
     String(String),
     Fn(Symbol),
+    Instance(Symbol),
     Callable(LoxCallable),
     Variable(usize, usize)
 }
@@ -57,7 +59,9 @@ impl LoxValue {
         match self {
             LoxValue::String(_) => 4,
             LoxValue::Number(_) => 4,
+            LoxValue::Integer(_) => 4,
             LoxValue::Boolean(_) => 1,
+            LoxValue::Instance(_) => 4,
             LoxValue::Variable(_, size) => *size,
             _ => 0
         }
@@ -67,11 +71,22 @@ impl LoxValue {
         match self {
             LoxValue::String(_) => "str".into(),
             LoxValue::Number(_) => "num".into(),
+            LoxValue::Integer(_) => "int".into(),
             LoxValue::Boolean(_) => "bool".into(),
             LoxValue::Variable(_, _) => "ref".into(),
             LoxValue::Void => "void".into(),
             LoxValue::Callable(n) => n.get_name(),
-            LoxValue::Fn(n) => format!("fn {}", n) 
+            LoxValue::Fn(n) => format!("fn {}", n),
+            LoxValue::Instance(n) => format!("class {}", n)
+        }
+    }
+
+    // `r#type` with the names of functions and classes resolved, for diagnostics.
+    pub fn type_name(&self, symbol_table: &SymbolTable) -> String {
+        match self {
+            LoxValue::Fn(name) => format!("fn {}", symbol_table.resolve(*name)),
+            LoxValue::Instance(name) => symbol_table.resolve(*name).to_string(),
+            _ => self.r#type(),
         }
     }
 }
@@ -80,6 +95,7 @@ impl LoxValue {
     pub(crate) fn negate_if_num(self) -> Result<Self, LoxValueError> {
         match self {
             LoxValue::Number(num) => Ok(LoxValue::Number(-num)),
+            LoxValue::Integer(int) => Ok(LoxValue::Integer(int.wrapping_neg())),
             _ => Err(LoxValueError::IncorrectOperand(
                 "Operand must be a number.".to_string(),
             )),
@@ -108,17 +124,41 @@ impl LoxValue {
 
     pub(crate) fn math_if_num(self, other: Self, operator: &TokenType) -> Result<Self, LoxValueError> {
         match (self, other) {
+            (LoxValue::Integer(left_int), LoxValue::Integer(right_int)) => {
+                // Ints wrap around like they do on the VM.
+                let result = match operator {
+                    TokenType::Plus => left_int.wrapping_add(right_int),
+                    TokenType::Minus => left_int.wrapping_sub(right_int),
+                    TokenType::Star => left_int.wrapping_mul(right_int),
+                    TokenType::Slash | TokenType::Percent if right_int == 0 => {
+                        return Err(LoxValueError::DivideByZero("Division by zero.".to_string()));
+                    }
+                    TokenType::Slash => left_int.wrapping_div(right_int),
+                    TokenType::Percent => left_int.wrapping_rem(right_int),
+                    TokenType::Ampersand => left_int & right_int,
+                    TokenType::Pipe => left_int | right_int,
+                    TokenType::Caret => left_int ^ right_int,
+                    TokenType::LessLess => left_int.wrapping_shl(right_int as u32),
+                    TokenType::GreaterGreater => left_int.wrapping_shr(right_int as u32),
+                    _ => {
+                        return Err(LoxValueError::IncorrectOperand(
+                            "Undefined operator on ints.".to_string(),
+                        ))
+                    }
+                };
+
+                Ok(LoxValue::Integer(result))
+            }
             (LoxValue::Number(left_num), LoxValue::Number(right_num)) => {
                 let result = match operator {
+                    TokenType::Slash | TokenType::Percent if right_num == 0.0 => {
+                        return Err(LoxValueError::DivideByZero("Division by zero.".to_string()));
+                    }
                     TokenType::Plus => left_num + right_num,
                     TokenType::Minus => left_num - right_num,
-                    TokenType::Slash => {
-                        if right_num == 0. {
-                            return Err(LoxValueError::DivideByZero("Divide by zero".to_string()));
-                        }
-                        left_num / right_num
-                    }
+                    TokenType::Slash => left_num / right_num,
                     TokenType::Star => left_num * right_num,
+                    TokenType::Percent => left_num % right_num,
                     _ => {
                         return Err(LoxValueError::IncorrectOperand(
                             "Undefined operator on numbers.".to_string(),
@@ -128,6 +168,16 @@ impl LoxValue {
 
                 Ok(LoxValue::Number(result))
             }
+            // Untyped scripts can mix both kinds of numbers, ints are widened.
+            (LoxValue::Integer(left_int), right @ LoxValue::Number(_)) => {
+                LoxValue::Number(left_int as f64).math_if_num(right, operator)
+            }
+            (left @ LoxValue::Number(_), LoxValue::Integer(right_int)) => {
+                left.math_if_num(LoxValue::Number(right_int as f64), operator)
+            }
+            (LoxValue::String(left_str), LoxValue::String(right_str)) if *operator == TokenType::Plus => {
+                Ok(LoxValue::String(left_str + &right_str))
+            }
             _ => Err(LoxValueError::IncorrectOperand(
                 "Operands must be numbers.".to_string(),
             )),
@@ -135,26 +185,31 @@ impl LoxValue {
     }
 
     pub(crate) fn compare_if_num(self, other: Self, operator: TokenType) -> Result<Self, LoxValueError> {
-        match (self, other) {
-            (LoxValue::Number(left_num), LoxValue::Number(right_num)) => {
-                let bool_value = match operator {
-                    TokenType::Greater => left_num > right_num,
-                    TokenType::GreaterEqual => left_num >= right_num,
-                    TokenType::Less => left_num < right_num,
-                    TokenType::LessEqual => left_num <= right_num,
-                    _ => {
-                        return Err(LoxValueError::IncorrectOperand(
-                            "Undefined comparator on numbers.".to_string(),
-                        ))
-                    }
-                };
+        let (left_num, right_num) = match (self, other) {
+            (LoxValue::Number(left_num), LoxValue::Number(right_num)) => (left_num, right_num),
+            (LoxValue::Integer(left_int), LoxValue::Integer(right_int)) => (left_int as f64, right_int as f64),
+            (LoxValue::Integer(left_int), LoxValue::Number(right_num)) => (left_int as f64, right_num),
+            (LoxValue::Number(left_num), LoxValue::Integer(right_int)) => (left_num, right_int as f64),
+            _ => {
+                return Err(LoxValueError::IncorrectOperand(
+                    "Operands must be numbers.".to_string(),
+                ))
+            }
+        };
 
-                Ok(LoxValue::Boolean(bool_value))
+        let bool_value = match operator {
+            TokenType::Greater => left_num > right_num,
+            TokenType::GreaterEqual => left_num >= right_num,
+            TokenType::Less => left_num < right_num,
+            TokenType::LessEqual => left_num <= right_num,
+            _ => {
+                return Err(LoxValueError::IncorrectOperand(
+                    "Undefined comparator on numbers.".to_string(),
+                ))
             }
-            _ => Err(LoxValueError::IncorrectOperand(
-                "Operands must be numbers.".to_string(),
-            )),
-        }
+        };
+
+        Ok(LoxValue::Boolean(bool_value))
     }
 
     #[allow(clippy::wrong_self_convention)]
@@ -165,7 +220,12 @@ impl LoxValue {
             }
             (LoxValue::String(left_str), LoxValue::String(right_str)) => left_str == right_str,
             (LoxValue::Number(left_num), LoxValue::Number(right_num)) => left_num == right_num,
+            (LoxValue::Integer(left_int), LoxValue::Integer(right_int)) => left_int == right_int,
+            (LoxValue::Integer(left_int), LoxValue::Number(right_num))
+            | (LoxValue::Number(right_num), LoxValue::Integer(left_int)) => left_int as f64 == right_num,
             (LoxValue::Void, LoxValue::Void) => true,
+            // Classes and functions are only equal to themselves.
+            (LoxValue::Callable(left), LoxValue::Callable(right)) => left.same_as(&right),
             _ => false,
         };
 
@@ -178,14 +238,28 @@ impl From<&Literal> for LoxValue {
         match literal {
             Literal::Str(str) => Self::String(str.clone()),
             Literal::Num(num) => Self::Number(f64::from(num)),
+            Literal::Int(int) => Self::Integer(*int),
             Literal::Void => Self::Void,
             Literal::True => Self::Boolean(true),
             Literal::False => Self::Boolean(false),
+            Literal::Class(name) => Self::Instance(*name),
         }
     }
 }
 
 
+impl LoxCallable {
+    fn same_as(&self, other: &LoxCallable) -> bool {
+        match (self, other) {
+            (LoxCallable::Function(left), LoxCallable::Function(right)) => Rc::ptr_eq(left, right),
+            (LoxCallable::Class(left), LoxCallable::Class(right)) => Rc::ptr_eq(left, right),
+            (LoxCallable::Instance(left), LoxCallable::Instance(right)) => Rc::ptr_eq(left, right),
+            (LoxCallable::NativeFunction(left), LoxCallable::NativeFunction(right)) => left == right,
+            _ => false,
+        }
+    }
+}
+
 impl Callable for LoxCallable {
     fn arity(&self, symbol_table: &mut SymbolTable) -> usize {
         match self {
@@ -201,16 +275,16 @@ impl Callable for LoxCallable {
 
     fn call(
         &self,
-        interpreter: &mut Interpreter,
+        evaluator: &mut Evaluator,
         arguments: Vec<LoxValue>,
     ) -> Result<LoxValue, RuntimeError> {
         match self {
-            LoxCallable::Function(function) => function.as_ref().call(interpreter, arguments),
-            LoxCallable::Class(class) => class.as_ref().call(interpreter, arguments),
-            LoxCallable::Instance(instance) => instance.borrow().call(interpreter, arguments),
+            LoxCallable::Function(function) => function.as_ref().call(evaluator, arguments),
+            LoxCallable::Class(class) => class.as_ref().call(evaluator, arguments),
+            LoxCallable::Instance(instance) => instance.borrow().call(evaluator, arguments),
             LoxCallable::NativeFunction(native_function) => match native_function {
-                NativeFunctions::ClockFunction(clock) => clock.as_ref().call(interpreter, arguments),
-                NativeFunctions::InputFunction(input) => input.as_ref().call(interpreter, arguments),
+                NativeFunctions::ClockFunction(clock) => clock.as_ref().call(evaluator, arguments),
+                NativeFunctions::InputFunction(input) => input.as_ref().call(evaluator, arguments),
             },
         }
     }
@@ -243,6 +317,7 @@ impl fmt::Display for LoxValue {
 
                 str_num
             }
+            LoxValue::Integer(int) => int.to_string(),
             LoxValue::Boolean(bool) => bool.to_string(),
             LoxValue::Void => "void".to_string(),
             LoxValue::Callable(callable) => {
@@ -287,7 +362,9 @@ impl PartialEq for LoxValue {
             (LoxValue::Void, LoxValue::Void) => true,
             (LoxValue::Boolean(a), LoxValue::Boolean(b)) => a == b,
             (LoxValue::Number(a), LoxValue::Number(b)) => a == b,
+            (LoxValue::Integer(a), LoxValue::Integer(b)) => a == b,
             (LoxValue::String(a), LoxValue::String(b)) => a == b,
+            (LoxValue::Instance(a), LoxValue::Instance(b)) => a == b,
             // Comparing callables directly is usually not meaningful
             (LoxValue::Callable(_), LoxValue::Callable(_)) => false,
             _ => false,
@@ -1,45 +1,94 @@
-use std::env::{self, args};
+use std::env::args;
 use std::process::exit;
 
-use rlox_jasm::lox;
-
-macro_rules! log_if_err {
-    ($expr:expr) => {
-        if let Err(err) = $expr {
-            eprintln!("Error: {}", err);
-        }
-    };
-}
+use rlox_jasm::cli::{self, Command};
+use rlox_jasm::lox::{self, LoxError};
 
 fn main() {
     let args: Vec<String> = args().collect();
-    let args_str: Vec<&str> = args[1..].iter().map(String::as_str).collect();
-    env::set_var("RUST_BACKTRACE", "1");
-
-    match args_str.as_slice() {
-        ["run", files @ ..] if !files.is_empty() => {
-            log_if_err!(lox::run_files(files));
-        }
-        ["build", files @ ..] if !files.is_empty() => {
-            log_if_err!(lox::build_files(files));
+    let cli = match cli::parse(&args[1..]) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("Error: {}\nRun 'rlox-jasm help' for usage.", message);
+            exit(64);
         }
-        ["jasm", files @ ..] if !files.is_empty() => {
-            log_if_err!(lox::jasm_files(files));
+    };
+
+    let options = &cli.options;
+    let result: Result<(), LoxError> = match &cli.command {
+        Command::Run(files) if cli.csr => lox::run_files_csr(&strs(files), options),
+        Command::Run(files) => lox::run_files(&strs(files), options),
+        Command::Build(files) => lox::build_files(&strs(files), options).map(drop),
+        Command::Jasm(files) => lox::jasm_files(&strs(files), options).map(drop),
+        Command::Check(files) => lox::check_files(&strs(files), options),
+        Command::Fmt { files, check } => lox::format_files(&strs(files), *check).map(|unformatted| {
+            for file in &unformatted {
+                println!("{} {}", if *check { "Unformatted" } else { "Formatted" }, file);
+            }
+            if *check && !unformatted.is_empty() {
+                exit(1);
+            }
+        }),
+        Command::Repl => lox::run_prompt(options),
+        Command::Interpret(files) => lox::interpret_files(&strs(files)),
+        Command::Difftest(dirs) => lox::difftest_dirs(&strs(dirs)),
+        Command::Help => {
+            print_usage();
+            Ok(())
         }
-        ["help", ..] | _ => print_usage()
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(err.exit_code());
     }
 }
 
+fn strs(args: &[String]) -> Vec<&str> {
+    args.iter().map(String::as_str).collect()
+}
+
 fn print_usage() {
     println!(
 "
 rlox-jasm --- A JASM IL and Bytecode generating lox compiler written in Rust.
 
-Usaage:
-    rlox-jasm run   <..files..>     : Convert all given source files to JASM Bytecode and execute.
-    rlox-jasm build <..files..>     : Convert all given source files to JASM Bytecode, but don't execute.
-    rlox-jasm jasm  <..files..>     : Convert all given source files to JASM IL.
-    rlox-jasm help                  : Print this message.
+Usage:
+    rlox-jasm [options] <command> [options]
+
+Commands:
+    run   <..files..>               : Compile all given source files and execute them on the embedded VM.
+    build <..files..>               : Convert all given source files to JASM Bytecode with JASM, but don't execute.
+    jasm  <..files..>               : Convert all given source files to JASM IL.
+    check <..files..>               : Compile all given source files and report errors and warnings, write nothing.
+    fmt   <..files..>               : Re-indent all given source files in place. With --check only list the ones
+                                      that would change, and fail if there are any.
+    repl                            : Compile and run lines as they are typed. Functions stay defined, the values
+                                      of expressions are printed.
+    interpret <..files..>           : Run all given source files on the tree-walking interpreter, types are optional.
+    difftest <..dirs..>             : Run the .lox programs in the given directories interpreted and compiled, and compare
+                                      both against their `// expect:` comments.
+    help                            : Print this message.
+
+Options:
+    -O, -O1, --opt-level <0|1>      : Run the peephole optimizer over the generated IL (level 1), or don't (level 0).
+    --annotate                      : Interleave source lines, frame layouts and label references into JASM IL.
+    -g, --emit-debug-info           : Map code addresses of every `.jef` that `build` writes to source lines. Not
+                                      supported yet, it needs the layout of JASM's `.jef` files.
+    -o <file>                       : Write the output of `build` or `jasm` to <file>, takes a single source file.
+    --out-dir <dir>                 : Write the outputs of `build` and `jasm` into <dir> instead of next to the sources.
+    --stack-size <bytes>            : Stack the program asks for, 1032 by default.
+    --heap-size <bytes>             : Heap the program asks for, 1024 by default.
+    --csr                           : Build with JASM and execute with CSR instead of the embedded VM.
+    --csr-path <path>               : CSR executable, the one next to rlox-jasm by default.
+    --jasm-path <path>              : JASM executable, the one next to rlox-jasm by default.
+
+Warnings:
+    -Wno-<name>                     : Disable the named warning. Files can do the same with `//! allow(<name>, ...)`.
+    -w                              : Disable all warnings.
+    Names: unreachable-code, unused-variable, unused-parameter, unused-function, shadowing
+
+Exit codes:
+    64 usage error, 65 compile error, 70 runtime error, 74 IO error.
 ");
-    exit(64);
 }
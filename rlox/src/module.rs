@@ -0,0 +1,279 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use rustc_hash::FxHashMap;
+
+use crate::analysis::WarningFilter;
+use crate::expr::{Expr, ExprPool};
+use crate::lexer::scanner::Scanner;
+use crate::lexer::token::Token;
+use crate::lox::{self, LoxError};
+use crate::parser::Parser;
+use crate::stmt::Stmt;
+use crate::symbol::{Symbol, SymbolTable};
+
+// A parsed source file. Top level functions of imported modules are renamed to `<module>.<name>`,
+// the file the compilation started from keeps its names, so its `main` is the only entry point.
+pub struct Module {
+    // `None` for the file the compilation started from.
+    pub name: Option<String>,
+    pub path: PathBuf,
+    pub statements: Vec<Stmt>,
+    // Warnings the file's own `//! allow(...)` pragmas switch off, only for its own statements.
+    pub allowed: WarningFilter,
+}
+
+// Every module of a program, after the modules it imports. All of them share one pool.
+pub struct Program {
+    pub modules: Vec<Module>,
+    pub expr_pool: ExprPool,
+    pub symbol_table: SymbolTable,
+}
+
+impl Program {
+    pub fn statements(&self) -> Vec<Stmt> {
+        self.modules.iter().flat_map(|module| module.statements.iter().cloned()).collect()
+    }
+}
+
+// Loads `source` and everything it imports. Import paths are relative to the importing file.
+pub fn link(source: &str, path: &Path) -> Result<Program, LoxError> {
+    let mut loader = Loader {
+        symbol_table: SymbolTable::new(),
+        expr_pool: ExprPool { exprs: Vec::new() },
+        modules: Vec::new(),
+        exprs: Vec::new(),
+        imports: Vec::new(),
+        loaded: FxHashMap::default(),
+        loading: Vec::new(),
+    };
+    // Sources that don't come from a file can't be imported back, their path doesn't matter.
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    loader.load(source, path, None)?;
+    loader.qualify()?;
+    Ok(Program { modules: loader.modules, expr_pool: loader.expr_pool, symbol_table: loader.symbol_table })
+}
+
+struct Loader {
+    symbol_table: SymbolTable,
+    expr_pool: ExprPool,
+    modules: Vec<Module>,
+    // The pool range and the imported modules, by name, of every entry in `modules`.
+    exprs: Vec<Range<usize>>,
+    imports: Vec<FxHashMap<String, usize>>,
+    loaded: FxHashMap<PathBuf, usize>,
+    // Modules whose imports are being loaded, outermost first.
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn load(&mut self, source: &str, path: PathBuf, name: Option<String>) -> Result<usize, LoxError> {
+        let mut allowed = WarningFilter::default();
+        allowed.apply_pragmas(source)?;
+        let tokens = {
+            let mut lexer = Scanner::new(source, &mut self.symbol_table);
+            lexer.scan_tokens();
+
+            lexer.tokens
+        };
+        let start = self.expr_pool.exprs.len();
+        let pool = std::mem::replace(&mut self.expr_pool, ExprPool { exprs: Vec::new() });
+        let (statements, pool) = Parser::new(&self.symbol_table, tokens)
+            .with_pool(pool)
+            .parse()
+            .map_err(|_| LoxError::Error(format!("Error during parsing '{}'", path.display())))?;
+        self.expr_pool = pool;
+        lox::check_errors()?;
+        let exprs = start..self.expr_pool.exprs.len();
+
+        self.loading.push(path.clone());
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut imports = FxHashMap::default();
+        for stmt in &statements {
+            if let Stmt::Import { keyword, path } = stmt {
+                let idx = self.import(keyword, &dir.join(path))?;
+                let name = self.modules[idx].name.clone().unwrap_or_default();
+                imports.insert(name, idx);
+            }
+        }
+        self.loading.pop();
+
+        if let Some(other) = self.modules.iter().find(|module| name.is_some() && module.name == name) {
+            return Err(LoxError::CompilationError(format!(
+                "Modules '{}' and '{}' are both named '{}'.",
+                other.path.display(),
+                path.display(),
+                name.unwrap_or_default()
+            )));
+        }
+        self.modules.push(Module { name, path, statements, allowed });
+        self.exprs.push(exprs);
+        self.imports.push(imports);
+        Ok(self.modules.len() - 1)
+    }
+
+    fn import(&mut self, keyword: &Token, path: &Path) -> Result<usize, LoxError> {
+        let error = |message: String| LoxError::CompilationError(format!("[line {}] {}", keyword.line, message));
+        let path = path
+            .canonicalize()
+            .map_err(|err| error(format!("Can't import '{}': {}.", path.display(), err)))?;
+        if let Some(idx) = self.loading.iter().position(|loading| *loading == path) {
+            let cycle: Vec<String> = self.loading[idx..].iter().chain([&path]).map(|path| path.display().to_string()).collect();
+            return Err(error(format!("Import cycle: {}.", cycle.join(" -> "))));
+        }
+        if let Some(&idx) = self.loaded.get(&path) {
+            return Ok(idx);
+        }
+
+        // The module is referred to by its file name, so it has to be a valid identifier.
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| stem.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_'))
+            .filter(|stem| stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+            .ok_or_else(|| error(format!("Can't import '{}': the file name isn't a valid module name.", path.display())))?
+            .to_string();
+        let source = fs::read_to_string(&path)?;
+        let idx = self.load(&source, path.clone(), Some(name))?;
+        self.loaded.insert(path, idx);
+        Ok(idx)
+    }
+
+    // Renames the top level functions of imported modules, along with the calls to them:
+    // `f()` inside a module and `module.f()` in the modules importing it.
+    fn qualify(&mut self) -> Result<(), LoxError> {
+        let mut functions: Vec<FxHashMap<Symbol, Symbol>> = Vec::new();
+        for module in &self.modules {
+            let mut names = FxHashMap::default();
+            for stmt in &module.statements {
+                if let Stmt::Function { name, .. } = stmt {
+                    let qualified = match &module.name {
+                        Some(module) => self.symbol_table.intern(&format!("{}.{}", module, self.symbol_table.resolve(name.lexeme))),
+                        None => name.lexeme,
+                    };
+                    names.insert(name.lexeme, qualified);
+                }
+            }
+            functions.push(names);
+        }
+
+        for (idx, module) in self.modules.iter_mut().enumerate() {
+            for stmt in &mut module.statements {
+                if let Stmt::Function { name, .. } = stmt {
+                    name.lexeme = functions[idx][&name.lexeme];
+                }
+            }
+            for expr in self.exprs[idx].clone() {
+                let Expr::Call { callee, .. } = self.expr_pool.exprs[expr] else { continue };
+                let renamed = match self.expr_pool.get_expr(callee) {
+                    Expr::Variable { name } => functions[idx].get(&name.lexeme).map(|&lexeme| Token { lexeme, ..name.clone() }),
+                    Expr::Get { object, name } => match self.expr_pool.get_expr(*object) {
+                        Expr::Variable { name: module } => match self.imports[idx].get(self.symbol_table.resolve(module.lexeme)) {
+                            Some(&target) => match functions[target].get(&name.lexeme) {
+                                Some(&lexeme) => Some(Token { lexeme, ..name.clone() }),
+                                None => {
+                                    return Err(LoxError::CompilationError(format!(
+                                        "[line {}] Module '{}' has no function '{}'.",
+                                        name.line,
+                                        self.symbol_table.resolve(module.lexeme),
+                                        self.symbol_table.resolve(name.lexeme)
+                                    )))
+                                }
+                            },
+                            None => None,
+                        },
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(name) = renamed {
+                    self.expr_pool.replace_expr(callee, Expr::Variable { name });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::analysis::WarningKind;
+    use crate::lox::CompileOptions;
+
+    // Files of a test in a fresh directory, which is removed again when this is dropped.
+    struct TempFiles {
+        dir: PathBuf,
+        // The first file.
+        main: PathBuf,
+    }
+
+    impl Drop for TempFiles {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn write_files(test: &str, files: &[(&str, &str)]) -> TempFiles {
+        let dir = env::temp_dir().join(format!("rlox-jasm-{}-{:?}", test, std::thread::current().id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        TempFiles { main: dir.join(files[0].0), dir }
+    }
+
+    fn run(path: &Path) -> Result<String, LoxError> {
+        let mut out = Vec::new();
+        lox::run_file(path, &CompileOptions::default(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_imports_are_linked() {
+        let files = write_files("imports", &[
+            ("main.lox", "import \"lib/shapes.lox\";\nimport \"lib/text.lox\";\nfn main() -> void { if (shapes.area(2) == 4) { print(text.main()); } }"),
+            ("lib/shapes.lox", "import \"text.lox\";\nfn square(x: int) -> int { return x * x; }\nfn area(x: int) -> int { print(text.main()); return square(x); }"),
+            ("lib/text.lox", "fn main() -> str { return \"hi\"; }"),
+        ]);
+        assert_eq!(run(&files.main).unwrap(), "hi\nhi\n");
+
+        let source = fs::read_to_string(&files.main).unwrap();
+        let program = link(&source, &files.main).unwrap();
+        let names: Vec<_> = program.modules.iter().map(|module| module.name.as_deref()).collect();
+        assert_eq!(names, [Some("text"), Some("shapes"), None]);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let files = write_files("cycle", &[
+            ("a.lox", "import \"b.lox\";\nfn main() -> void { }"),
+            ("b.lox", "import \"a.lox\";"),
+        ]);
+        let err = run(&files.main).unwrap_err().to_string();
+        assert!(err.contains("Import cycle:") && err.ends_with("a.lox."), "{}", err);
+
+        let files = write_files("missing", &[
+            ("main.lox", "import \"math.lox\";\nfn main() -> void { let x = math.cube(2); }"),
+            ("math.lox", "fn square(x: int) -> int { return x * x; }"),
+        ]);
+        assert!(run(&files.main).unwrap_err().to_string().contains("[line 2] Module 'math' has no function 'cube'."));
+    }
+
+    #[test]
+    fn test_pragmas_are_per_module() {
+        let files = write_files("pragmas", &[
+            ("main.lox", "import \"lib.lox\";\nfn main() -> void { let unused = lib.f(); }"),
+            ("lib.lox", "//! allow(unused-variable)\nfn f() -> int { let unused = 1; return 2; }"),
+        ]);
+        let source = fs::read_to_string(&files.main).unwrap();
+        let program = link(&source, &files.main).unwrap();
+        let allowed: Vec<_> = program.modules.iter().map(|module| module.allowed.is_enabled(WarningKind::UnusedVariable)).collect();
+        assert_eq!(allowed, [false, true]);
+    }
+}
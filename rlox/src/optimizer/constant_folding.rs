@@ -0,0 +1,286 @@
+use std::rc::Rc;
+
+use rust_decimal::Decimal;
+
+use crate::expr::{Expr, ExprIdx, ExprPool};
+use crate::lexer::token::{Hf64, Literal, TokenType};
+use crate::stmt::Stmt;
+
+// Folds expressions over literals into a single literal. Runs after the analyses, so their
+// diagnostics still see the program as written. Codegen drops the branches whose condition
+// became a literal, after type checking them. Numbers are folded in `f32`, the precision
+// they have at runtime.
+pub struct ConstantFolding<'a> {
+    expr_pool: &'a mut ExprPool,
+}
+
+impl<'a> ConstantFolding<'a> {
+    pub fn new(expr_pool: &'a mut ExprPool) -> Self {
+        ConstantFolding { expr_pool }
+    }
+
+    pub fn fold(mut self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        self.statements(statements)
+    }
+
+    fn statements(&mut self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        statements.into_iter().map(|stmt| self.statement(stmt)).collect()
+    }
+
+    fn statement(&mut self, stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::Expression { expression } | Stmt::Print { expression } => {
+                self.expression(expression);
+                stmt
+            }
+            Stmt::Var { initializer, .. } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                stmt
+            }
+            Stmt::Block { statements } => Stmt::Block { statements: self.statements(statements) },
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.expression(condition);
+                Stmt::If { condition, then_branch: self.branch(&then_branch), else_branch: else_branch.map(|else_branch| self.branch(&else_branch)) }
+            }
+            Stmt::While { condition, body, increment } => {
+                self.expression(condition);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+                Stmt::While { condition, body: self.branch(&body), increment }
+            }
+            Stmt::Match { keyword, subject, mut arms, default } => {
+                self.expression(subject);
+                for arm in arms.iter_mut() {
+                    arm.body = self.branch(&arm.body);
+                }
+                Stmt::Match { keyword, subject, arms, default: default.map(|default| self.branch(&default)) }
+            }
+            Stmt::Function { name, params, return_type, body } => Stmt::Function { name, params, return_type, body: self.statements(body) },
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                stmt
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Class { .. } | Stmt::Import { .. } => stmt,
+        }
+    }
+
+    fn branch(&mut self, stmt: &Rc<Stmt>) -> Rc<Stmt> {
+        Rc::new(self.statement(stmt.as_ref().clone()))
+    }
+
+    // Folds the expression in place and returns its value if it is now a literal.
+    fn expression(&mut self, expr_idx: ExprIdx) -> Option<Literal> {
+        let folded = match self.expr_pool.get_expr(expr_idx).clone() {
+            Expr::Literal { value } => return Some(value),
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Unary { operator, right } => {
+                let right = self.expression(right)?;
+                match (operator.token_type, right) {
+                    (TokenType::Minus, Literal::Int(value)) => Some(Literal::Int(value.wrapping_neg())),
+                    (TokenType::Minus, Literal::Num(value)) => num(-float(value)),
+                    (TokenType::Bang, Literal::True) => Some(Literal::False),
+                    (TokenType::Bang, Literal::False) => Some(Literal::True),
+                    _ => None,
+                }
+            }
+            Expr::Binary { left, operator, right } => {
+                let (left, right) = (self.expression(left), self.expression(right));
+                binary(operator.token_type, left?, right?)
+            }
+            Expr::Logical { left, operator, right } => {
+                let (left, right) = (self.expression(left), self.expression(right));
+                // Only literal operands are folded, so type errors in the other operand are still reported.
+                match (operator.token_type, left?, right?) {
+                    (TokenType::And, Literal::True, right @ (Literal::True | Literal::False)) => Some(right),
+                    (TokenType::And, Literal::False, Literal::True | Literal::False) => Some(Literal::False),
+                    (TokenType::Or, Literal::True, Literal::True | Literal::False) => Some(Literal::True),
+                    (TokenType::Or, Literal::False, right @ (Literal::True | Literal::False)) => Some(right),
+                    _ => None,
+                }
+            }
+            Expr::Cast { target, expression } => match (target.token_type, self.expression(expression)?) {
+                (TokenType::Int, Literal::Num(value)) => Some(Literal::Int(float(value) as i32)),
+                (TokenType::Number, Literal::Int(value)) => num(value as f32),
+                (TokenType::Int, value @ Literal::Int(_)) | (TokenType::Number, value @ Literal::Num(_)) => Some(value),
+                _ => None,
+            },
+            Expr::Assign { value, .. } => {
+                self.expression(value);
+                None
+            }
+            Expr::Call { callee, arguments, .. } => {
+                self.expression(callee);
+                arguments.iter().for_each(|argument| {
+                    self.expression(*argument);
+                });
+                None
+            }
+            Expr::Get { object, .. } => {
+                self.expression(object);
+                None
+            }
+            Expr::Set { object, value, .. } => {
+                self.expression(value);
+                self.expression(object);
+                None
+            }
+            Expr::Variable { .. } | Expr::This { .. } | Expr::Super { .. } => None,
+        }?;
+        self.expr_pool.replace_expr(expr_idx, Expr::Literal { value: folded.clone() });
+        Some(folded)
+    }
+}
+
+// Literals are stored as decimals and assembled to `f32`, so they are rounded the same way first.
+fn float(value: Hf64) -> f32 {
+    f64::from(value) as f32
+}
+
+// Numbers that don't fit a literal (infinities, NaN) are left to the runtime. The decimal keeps
+// every digit, so later folds and the assembler get back the same `f32`.
+fn num(value: f32) -> Option<Literal> {
+    Decimal::from_f32_retain(value).map(|value| Literal::Num(Hf64(value)))
+}
+
+fn boolean(value: bool) -> Option<Literal> {
+    Some(if value { Literal::True } else { Literal::False })
+}
+
+fn binary(operator: TokenType, left: Literal, right: Literal) -> Option<Literal> {
+    match (left, right) {
+        (Literal::Int(a), Literal::Int(b)) => match operator {
+            TokenType::Plus => Some(Literal::Int(a.wrapping_add(b))),
+            TokenType::Minus => Some(Literal::Int(a.wrapping_sub(b))),
+            TokenType::Star => Some(Literal::Int(a.wrapping_mul(b))),
+            // Division by zero is kept so that it fails at runtime, like it would unfolded.
+            TokenType::Slash => a.checked_div(b).map(Literal::Int),
+            TokenType::Percent => a.checked_rem(b).map(Literal::Int),
+            TokenType::Ampersand => Some(Literal::Int(a & b)),
+            TokenType::Pipe => Some(Literal::Int(a | b)),
+            TokenType::Caret => Some(Literal::Int(a ^ b)),
+            TokenType::LessLess => Some(Literal::Int(a.wrapping_shl(b as u32))),
+            TokenType::GreaterGreater => Some(Literal::Int(a.wrapping_shr(b as u32))),
+            _ => compare(operator, a.cmp(&b)),
+        },
+        (Literal::Num(a), Literal::Num(b)) => {
+            let (a, b) = (float(a), float(b));
+            match operator {
+                TokenType::Plus => num(a + b),
+                TokenType::Minus => num(a - b),
+                TokenType::Star => num(a * b),
+                TokenType::Slash if b != 0.0 => num(a / b),
+                TokenType::Slash => None,
+                _ => compare(operator, a.partial_cmp(&b)?),
+            }
+        }
+        (a @ (Literal::True | Literal::False), b @ (Literal::True | Literal::False)) => match operator {
+            TokenType::EqualEqual => boolean(a == b),
+            TokenType::BangEqual => boolean(a != b),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn compare(operator: TokenType, ordering: std::cmp::Ordering) -> Option<Literal> {
+    match operator {
+        TokenType::Less => boolean(ordering.is_lt()),
+        TokenType::LessEqual => boolean(ordering.is_le()),
+        TokenType::Greater => boolean(ordering.is_gt()),
+        TokenType::GreaterEqual => boolean(ordering.is_ge()),
+        TokenType::EqualEqual => boolean(ordering.is_eq()),
+        TokenType::BangEqual => boolean(ordering.is_ne()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::scanner::Scanner;
+    use crate::lox::{self, CompileOptions};
+    use crate::parser::Parser;
+    use crate::symbol::SymbolTable;
+
+    // Folds the body of the first function in the source.
+    fn fold_source(source: &str) -> (Vec<Stmt>, ExprPool) {
+        let mut symbol_table = SymbolTable::new();
+        let tokens = {
+            let mut scanner = Scanner::new(source, &mut symbol_table);
+            scanner.scan_tokens();
+            scanner.tokens
+        };
+        let (statements, mut expr_pool) = Parser::new(&symbol_table, tokens).parse().unwrap();
+        let statements = ConstantFolding::new(&mut expr_pool).fold(statements);
+        match statements.into_iter().next() {
+            Some(Stmt::Function { body, .. }) => (body, expr_pool),
+            _ => panic!("Expected a function"),
+        }
+    }
+
+    fn initializer(stmt: &Stmt, expr_pool: &ExprPool) -> Expr {
+        match stmt {
+            Stmt::Var { initializer: Some(initializer), .. } => expr_pool.get_expr(*initializer).clone(),
+            _ => panic!("Expected an initialized variable"),
+        }
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        let (body, pool) = fold_source("fn main() -> void { let a = (1 + 2) * 3 % 4; let b = 1.5 * 2.0; let c = num(3) / 2.0; }");
+        assert_eq!(initializer(&body[0], &pool), Expr::Literal { value: Literal::Int(1) });
+        assert_eq!(initializer(&body[1], &pool), Expr::Literal { value: Literal::Num(3.into()) });
+        assert_eq!(initializer(&body[2], &pool), Expr::Literal { value: Literal::Num(Hf64::try_from(1.5).unwrap()) });
+    }
+
+    #[test]
+    fn test_fold_comparison() {
+        let (body, pool) = fold_source("fn main() -> void { let a = 1 < 2 && !false; }");
+        assert_eq!(initializer(&body[0], &pool), Expr::Literal { value: Literal::True });
+    }
+
+    #[test]
+    fn test_division_by_zero_is_not_folded() {
+        let (body, pool) = fold_source("fn main() -> void { let a = 1 / 0; }");
+        assert!(matches!(initializer(&body[0], &pool), Expr::Binary { .. }));
+    }
+
+    #[test]
+    fn test_conditions_are_folded() {
+        let (body, pool) = fold_source("fn main() -> void { if (1 > 2) print(\"a\"); while (!true) { } match (1 + 1) { 2 => { } } }");
+        let conditions = body.iter().map(|stmt| match stmt {
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } | Stmt::Match { subject: condition, .. } => pool.get_expr(*condition).clone(),
+            _ => panic!("Expected a branch"),
+        });
+        let literals = [Literal::False, Literal::False, Literal::Int(2)].map(|value| Expr::Literal { value });
+        assert!(conditions.eq(literals));
+    }
+
+    // The same comparisons over literals, folded, and over variables, computed by the VM.
+    #[test]
+    fn test_folding_matches_runtime() {
+        let run = |declarations: &str, condition: &str| {
+            let source = format!("fn main() -> void {{ {} if ({}) {{ print(\"true\"); }} else {{ print(\"false\"); }} }}", declarations, condition);
+            let mut out = Vec::new();
+            lox::run_source(&source, &CompileOptions::default(), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        for (folded, declarations, unfolded) in [
+            ("16777216.0 + 1.0 == 16777216.0", "let a = 16777216.0; let b = 1.0;", "a + b == a"),
+            ("0.1 + 0.2 == 0.3", "let a = 0.1; let b = 0.2; let c = 0.3;", "a + b == c"),
+            ("1.0 / 3.0 * 3.0 == 1.0", "let a = 1.0; let b = 3.0;", "a / b * b == a"),
+            ("int(2.675 * 100.0) == 267", "let a = 2.675; let b = 100.0;", "int(a * b) == 267"),
+            ("-0.7 * 0.7 < -0.49", "let a = 0.7; let b = -0.49;", "-a * a < b"),
+            ("num(16777217) == 16777216.0", "let a = 16777217; let b = 16777216.0;", "num(a) == b"),
+        ] {
+            assert_eq!(run("", folded), run(declarations, unfolded), "{}", folded);
+        }
+        assert_eq!(run("", "16777216.0 + 1.0 == 16777216.0"), "true\n");
+    }
+
+}
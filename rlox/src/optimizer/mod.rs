@@ -0,0 +1,2 @@
+pub mod constant_folding;
+pub mod peephole;
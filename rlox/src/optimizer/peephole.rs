@@ -0,0 +1,224 @@
+use crate::il::{Il, IlLine, Instr, Reg, Size};
+
+// Rewrites short windows of generated IL until nothing changes anymore. Comments are
+// transparent to the patterns, labels and jumps are never touched so jump tables keep
+// their layout.
+pub fn optimize(il: &mut Il) {
+    while pass(il) {}
+}
+
+fn pass(il: &mut Il) -> bool {
+    let mut lines: Vec<Option<IlLine>> = std::mem::take(&mut il.lines).into_iter().map(Some).collect();
+    let changed = merge_stack_adjustments(&mut lines)
+        | drop_push_pop_pairs(&mut lines)
+        | drop_reloads(&mut lines)
+        | drop_address_recomputations(&mut lines);
+    il.lines = lines.into_iter().flatten().collect();
+    changed
+}
+
+// Indices of the remaining instructions and labels, skipping comments.
+fn code(lines: &[Option<IlLine>]) -> Vec<usize> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().is_some_and(|line| !matches!(line.instr, Instr::Comment(_))))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+fn instr(lines: &[Option<IlLine>], idx: usize) -> Option<&Instr> {
+    lines[idx].as_ref().map(|line| &line.instr)
+}
+
+// Bytes popped by `pop %t` or `dcr %i &sp N`.
+fn popped(instr: Option<&Instr>) -> Option<usize> {
+    match instr? {
+        Instr::Pop(size) => Some(size.bytes()),
+        Instr::Dcr(Size::Int, Reg::Sp, amount) => Some(*amount),
+        _ => None,
+    }
+}
+
+// Bytes pushed by an instruction that has no other effect.
+fn pure_push(instr: Option<&Instr>) -> Option<usize> {
+    match instr? {
+        Instr::Stc(value) => Some(value.size().bytes()),
+        Instr::Rda(size @ (Size::Int | Size::Byte)) => Some(size.bytes()),
+        _ => None,
+    }
+}
+
+// `dcr %i &sp A` + `dcr %i &sp B` => `dcr %i &sp A+B`, same for `inc %i &ebx`.
+fn merge_stack_adjustments(lines: &mut [Option<IlLine>]) -> bool {
+    let mut changed = false;
+    let code = code(lines);
+    for pair in code.windows(2) {
+        let (first, second) = (pair[0], pair[1]);
+        let merged = match (instr(lines, first), instr(lines, second)) {
+            (Some(Instr::Dcr(Size::Int, Reg::Sp, a)), Some(Instr::Dcr(Size::Int, Reg::Sp, b))) => Instr::Dcr(Size::Int, Reg::Sp, a + b),
+            (Some(Instr::Inc(Size::Int, Reg::Ebx, a)), Some(Instr::Inc(Size::Int, Reg::Ebx, b))) => Instr::Inc(Size::Int, Reg::Ebx, a + b),
+            _ => continue,
+        };
+        lines[first] = None;
+        if let Some(line) = lines[second].as_mut() {
+            line.instr = merged;
+        }
+        changed = true;
+    }
+    changed
+}
+
+// A value that is pushed and popped right away never had any effect.
+fn drop_push_pop_pairs(lines: &mut [Option<IlLine>]) -> bool {
+    let mut changed = false;
+    let code = code(lines);
+    for pair in code.windows(2) {
+        let (first, second) = (pair[0], pair[1]);
+        let (Some(pushed), Some(popped)) = (pure_push(instr(lines, first)), popped(instr(lines, second))) else { continue };
+        if popped == pushed {
+            lines[first] = None;
+            lines[second] = None;
+        } else if popped > pushed {
+            lines[first] = None;
+            if let Some(line) = lines[second].as_mut() {
+                line.instr = Instr::Dcr(Size::Int, Reg::Sp, popped - pushed);
+            }
+        } else {
+            continue;
+        }
+        changed = true;
+    }
+    changed
+}
+
+// `ldc %t`, popping the stored value and reading it back from the same slot leaves the stack as it was.
+fn drop_reloads(lines: &mut [Option<IlLine>]) -> bool {
+    let mut changed = false;
+    let code = code(lines);
+    for window in code.windows(3) {
+        let size = match (instr(lines, window[0]), instr(lines, window[2])) {
+            (Some(Instr::Ldc(stored)), Some(Instr::Rda(loaded))) if stored == loaded && *stored != Size::Float => stored.bytes(),
+            _ => continue,
+        };
+        if popped(instr(lines, window[1])) == Some(size) {
+            lines[window[1]] = None;
+            lines[window[2]] = None;
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Instructions that leave `&ebx` alone. Anything else, labels included, forgets its value.
+fn keeps_ebx(instr: &Instr) -> bool {
+    // Only the stack forms, `add %i &ecx &ebx` and friends do write registers.
+    matches!(
+        instr,
+        Instr::Arith(..)
+            | Instr::Pop(_)
+            | Instr::Ldc(_)
+            | Instr::Stc(_)
+            | Instr::Cmp(..)
+            | Instr::Cnv(..)
+            | Instr::Rda(Size::Int | Size::Byte)
+            | Instr::RdaReg(Reg::Bl)
+            | Instr::MovTop(Reg::Bl)
+            | Instr::Dcr(Size::Int, Reg::Sp, _)
+    )
+}
+
+// `mov &bp &ebx` (+ `inc %i &ebx N`) is dropped when `&ebx` already holds that address.
+fn drop_address_recomputations(lines: &mut [Option<IlLine>]) -> bool {
+    let mut changed = false;
+    // Offset from `&bp` currently held by `&ebx`, if known.
+    let mut ebx: Option<usize> = None;
+    let code = code(lines);
+    let mut idx = 0;
+    while idx < code.len() {
+        let Some(current) = instr(lines, code[idx]) else {
+            idx += 1;
+            continue;
+        };
+        if *current == Instr::MovReg(Reg::Bp, Reg::Ebx) {
+            let offset = code.get(idx + 1).and_then(|next| match instr(lines, *next) {
+                Some(Instr::Inc(Size::Int, Reg::Ebx, offset)) => Some(*offset),
+                _ => None,
+            });
+            let span = if offset.is_some() { 2 } else { 1 };
+            let offset = offset.unwrap_or(0);
+            if ebx == Some(offset) {
+                for remove in &code[idx..idx + span] {
+                    lines[*remove] = None;
+                }
+                changed = true;
+            }
+            ebx = Some(offset);
+            idx += span;
+            continue;
+        }
+        if !keeps_ebx(current) {
+            ebx = None;
+        }
+        idx += 1;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::il::Constant;
+
+    fn optimized(instrs: Vec<Instr>) -> Vec<Instr> {
+        let mut il = Il::new();
+        instrs.into_iter().for_each(|instr| il.push(0, instr));
+        optimize(&mut il);
+        il.lines.into_iter().map(|line| line.instr).collect()
+    }
+
+    fn sp(amount: usize) -> Instr {
+        Instr::Dcr(Size::Int, Reg::Sp, amount)
+    }
+
+    fn slot(offset: usize) -> [Instr; 2] {
+        [Instr::MovReg(Reg::Bp, Reg::Ebx), Instr::Inc(Size::Int, Reg::Ebx, offset)]
+    }
+
+    #[test]
+    fn test_merge_stack_adjustments() {
+        assert_eq!(optimized(vec![sp(4), Instr::comment("comment"), sp(1)]), vec![Instr::comment("comment"), sp(5)]);
+    }
+
+    #[test]
+    fn test_push_pop_pairs() {
+        let instrs = vec![Instr::Stc(Constant::Int(3)), Instr::Pop(Size::Int), Instr::Stc(Constant::Byte(1)), sp(5)];
+        assert_eq!(optimized(instrs), vec![sp(4)]);
+    }
+
+    #[test]
+    fn test_reload_after_store() {
+        let mut instrs = vec![Instr::Stc(Constant::Int(1))];
+        instrs.extend(slot(4));
+        instrs.extend([Instr::Ldc(Size::Int), sp(4)]);
+        instrs.extend(slot(4));
+        instrs.push(Instr::Rda(Size::Int));
+
+        let mut expected = vec![Instr::Stc(Constant::Int(1))];
+        expected.extend(slot(4));
+        expected.push(Instr::Ldc(Size::Int));
+        assert_eq!(optimized(instrs), expected);
+    }
+
+    #[test]
+    fn test_address_is_recomputed_after_labels() {
+        let instrs = vec![
+            Instr::MovReg(Reg::Bp, Reg::Ebx),
+            Instr::Rda(Size::Int),
+            Instr::Label("_while_start_0".into()),
+            Instr::MovReg(Reg::Bp, Reg::Ebx),
+            Instr::Rda(Size::Int),
+        ];
+        assert_eq!(optimized(instrs.clone()), instrs);
+    }
+}
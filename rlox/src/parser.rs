@@ -4,7 +4,7 @@ use crate::expr::{Expr, ExprIdx, ExprPool};
 use crate::lexer::token::{ErrorToken, Hf64, Literal, Token, TokenType};
 use crate::lox;
 use crate::lox_value::LoxValue;
-use crate::stmt::Stmt;
+use crate::stmt::{MatchArm, Stmt};
 use crate::symbol::{Symbol, SymbolTable};
 
 #[derive(Debug, Clone)]
@@ -14,19 +14,41 @@ pub struct Parser<'a> {
     tokens: Vec<Token>,
     current: usize,
     expr_pool: ExprPool,
-    symbol_table: &'a SymbolTable
+    symbol_table: &'a SymbolTable,
+    // Type annotations are optional, for scripts run by the tree-walking evaluator.
+    untyped: bool,
+    // Classes declared anywhere in the file, the only names that can be used as types.
+    classes: Vec<Symbol>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(symbol_table: &'a SymbolTable, tokens: Vec<Token>) -> Self {
+        let classes = tokens
+            .windows(2)
+            .filter(|pair| pair[0].token_type == TokenType::Class && pair[1].token_type == TokenType::Identifier)
+            .map(|pair| pair[1].lexeme)
+            .collect();
         Self {
             tokens,
+            classes,
             current: 0,
             expr_pool: ExprPool { exprs: Vec::with_capacity(9999) },
-            symbol_table
+            symbol_table,
+            untyped: false,
         }
     }
 
+    pub fn untyped(mut self) -> Self {
+        self.untyped = true;
+        self
+    }
+
+    // Parses into an existing pool, so files parsed one after the other can be compiled together.
+    pub fn with_pool(mut self, expr_pool: ExprPool) -> Self {
+        self.expr_pool = expr_pool;
+        self
+    }
+
     pub fn parse(mut self) -> Result<(Vec<Stmt>, ExprPool), ParseError> {
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
@@ -36,6 +58,10 @@ impl<'a> Parser<'a> {
     }
 
     fn declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_types(&[TokenType::Import]) {
+            return self.import_declaration();
+        }
+
         if self.match_types(&[TokenType::Class]) {
             return self.class_declaration();
         }
@@ -61,6 +87,18 @@ impl<'a> Parser<'a> {
         stmt_result // Return the result of the statement (either Ok or Err)
     }
 
+    // importDecl -> "import" STRING ";" ;
+    fn import_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        let path = match self.peek() {
+            Token { token_type: TokenType::String, literal: Literal::Str(path), .. } => path,
+            token => return Err(self.error(token, "Expect module path after 'import'.")),
+        };
+        self.advance();
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import { keyword, path })
+    }
+
     fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name = self.consume(TokenType::Identifier, "Expect class name.")?;
 
@@ -106,12 +144,11 @@ impl<'a> Parser<'a> {
                 }
 
                 let mut identifier = self.consume(TokenType::Identifier, "Expected parameter name.")?;
-                self.consume(TokenType::Colon, "Expected colon after identifier.")?;
-                let token = self.advance();
-                if [TokenType::String, TokenType::Number, TokenType::Bool].iter().any(|x| *x == token.token_type) {
-                    identifier.literal = token.token_type.into();
-                } else { return Err(ParseError); }
-                        parameters.push(identifier);
+                if !self.untyped || self.check(&TokenType::Colon) {
+                    self.consume(TokenType::Colon, "Expected colon after identifier.")?;
+                    identifier.literal = self.type_annotation(false, "Expect parameter type after ':'.")?;
+                }
+                parameters.push(identifier);
 
                 if !self.match_types(&[TokenType::Comma]) {
                     break;
@@ -120,16 +157,13 @@ impl<'a> Parser<'a> {
         }
 
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
-        self.consume(TokenType::Arrow, "Expected '->' to denote return type.")?;
-
-        /*let returns = match self.advance().token_type {
-            TokenType::Number | TokenType::String => 4,
-            TokenType::Bool => 1,
-            TokenType::Void => 0,
-            _ => return Err(ParseError) 
-        };*/
-        let token: Literal = self.advance().into();
-        let returns: LoxValue = From::<&Literal>::from(&token);
+        let returns = if !self.untyped || self.check(&TokenType::Arrow) {
+            self.consume(TokenType::Arrow, "Expected '->' to denote return type.")?;
+            let token = self.type_annotation(true, "Expect return type after '->'.")?;
+            From::<&Literal>::from(&token)
+        } else {
+            LoxValue::Void
+        };
         self.consume(
             TokenType::LeftBrace,
             &format!("Expect '{{' after {} body.", kind),
@@ -148,21 +182,17 @@ impl<'a> Parser<'a> {
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
 
-        let mut t: Literal;
-        self.consume(TokenType::Colon, "Expected ':' after identifier.")?;
-        let token = self.advance();
-        if [TokenType::String, TokenType::Number, TokenType::Bool].iter().any(|x| *x == token.token_type) {
-            t = match token.token_type {
-                TokenType::String => Literal::Str("".into()),
-                TokenType::Number => Literal::Num(0f64.into()),
-                TokenType::Bool => Literal::True,
-                _ => unreachable!()
-            }
-        } else { return Err(ParseError); }
+        // Without an annotation the type is inferred from the initializer, marked by `Literal::Void`.
+        let mut t = Literal::Void;
+        if self.match_types(&[TokenType::Colon]) {
+            t = self.type_annotation(false, "Expect type after ':'.")?;
+        }
 
         let mut initializer = None;
         if self.match_types(&[TokenType::Equal]) {
             initializer = Some(self.expression()?);
+        } else if t == Literal::Void && !self.untyped {
+            return Err(self.error(self.peek(), "Expect type annotation or initializer in variable declaration."));
         }
 
         self.consume(
@@ -179,6 +209,30 @@ impl<'a> Parser<'a> {
         })
     }
 
+    // type -> "str" | "num" | "int" | "bool" | IDENTIFIER ;
+    // Return types additionally accept "void". Declared class names are types too, they are
+    // recorded as `Literal::Class` the same way builtin types use placeholder literals.
+    fn type_annotation(&mut self, allow_void: bool, message: &str) -> Result<Literal, ParseError> {
+        let token = self.peek();
+        // Type keywords share their token type with literals of that type, but carry no literal.
+        let is_keyword = token.literal == Literal::Void;
+        let annotation = match token.token_type {
+            TokenType::String if is_keyword => Literal::Str("".into()),
+            TokenType::Number if is_keyword => Literal::Num(0.into()),
+            TokenType::Int => Literal::Int(0),
+            TokenType::Bool => Literal::False,
+            TokenType::Void if allow_void => Literal::Void,
+            TokenType::Identifier if self.classes.contains(&token.lexeme) => Literal::Class(token.lexeme),
+            TokenType::Identifier => {
+                let message = format!("Unknown type '{}'.", self.symbol_table.resolve(token.lexeme));
+                return Err(self.error(token, &message));
+            }
+            _ => return Err(self.error(token, message)),
+        };
+        self.advance();
+        Ok(annotation)
+    }
+
     fn statement(&mut self) -> Result<Stmt, ParseError> {
         if self.match_types(&[TokenType::Print]) {
             return self.print_stmt();
@@ -188,6 +242,18 @@ impl<'a> Parser<'a> {
             return self.return_stmt();
         };
 
+        if self.match_types(&[TokenType::Break]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
+            return Ok(Stmt::Break { keyword });
+        };
+
+        if self.match_types(&[TokenType::Continue]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+            return Ok(Stmt::Continue { keyword });
+        };
+
         if self.match_types(&[TokenType::While]) {
             return self.while_stmt();
         }
@@ -205,6 +271,10 @@ impl<'a> Parser<'a> {
             return self.if_stmt();
         };
 
+        if self.match_types(&[TokenType::Match]) {
+            return self.match_stmt();
+        };
+
         self.expression_stmt()
     }
 
@@ -236,6 +306,7 @@ impl<'a> Parser<'a> {
         Ok(Stmt::While {
             condition,
             body: Rc::new(body),
+            increment: None,
         })
     }
 
@@ -269,25 +340,19 @@ impl<'a> Parser<'a> {
         }
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(increment_expr_idx) = increment {
-            body = Stmt::Block {
-                statements: vec![
-                    body,
-                    Stmt::Expression {
-                        expression: increment_expr_idx,
-                    },
-                ],
-            };
-        }
+        let body = self.statement()?;
 
-        if let Some(condition_expr_idx) = condition {
-            body = Stmt::While {
-                condition: condition_expr_idx,
-                body: Rc::new(body),
-            };
-        }
+        // The increment stays attached to the loop instead of being appended to the body,
+        // so that `continue` jumps to it rather than skipping it.
+        let condition = match condition {
+            Some(condition_expr_idx) => condition_expr_idx,
+            None => self.expr_pool.add_expr(Expr::Literal { value: Literal::True }),
+        };
+        let mut body = Stmt::While {
+            condition,
+            body: Rc::new(body),
+            increment,
+        };
 
         if let Some(initializer_stmt) = initializer {
             body = Stmt::Block {
@@ -316,6 +381,57 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn match_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'match'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after match subject.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before match arms.")?;
+
+        let mut arms = Vec::new();
+        let mut default = None;
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.match_types(&[TokenType::Else]) {
+                if default.is_some() {
+                    return Err(self.error(self.previous(), "Match statement can only have one 'else' arm."));
+                }
+                self.consume(TokenType::FatArrow, "Expect '=>' after 'else'.")?;
+                default = Some(Rc::new(self.statement()?));
+                continue;
+            }
+
+            let mut patterns = vec![self.match_pattern()?];
+            while self.match_types(&[TokenType::Comma]) {
+                patterns.push(self.match_pattern()?);
+            }
+            self.consume(TokenType::FatArrow, "Expect '=>' after match pattern.")?;
+            let body = Rc::new(self.statement()?);
+            arms.push(MatchArm { patterns, body });
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.")?;
+
+        Ok(Stmt::Match {
+            keyword,
+            subject,
+            arms,
+            default,
+        })
+    }
+
+    fn match_pattern(&mut self) -> Result<Literal, ParseError> {
+        let negative = self.match_types(&[TokenType::Minus]);
+        let token = self.advance();
+        match (token.token_type, token.literal, negative) {
+            (TokenType::Number, Literal::Int(v), _) => Ok(Literal::Int(if negative { -v } else { v })),
+            (TokenType::Number, Literal::Num(v), _) => Ok(Literal::Num(if negative { Hf64(-v.0) } else { v })),
+            (TokenType::String, literal @ Literal::Str(_), false) => Ok(literal),
+            (TokenType::True, _, false) => Ok(Literal::True),
+            (TokenType::False, _, false) => Ok(Literal::False),
+            _ => Err(self.error(self.previous(), "Expect literal match pattern.")),
+        }
+    }
+
     fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements = Vec::<Stmt>::new();
 
@@ -345,6 +461,7 @@ impl<'a> Parser<'a> {
                 Expr::Variable { name } => {
                     let assign_expr = Expr::Assign {
                         name: name.clone(),
+                        operator: None,
                         value: value_idx,
                     };
                     let idx = self.expr_pool.add_expr(assign_expr);
@@ -363,6 +480,34 @@ impl<'a> Parser<'a> {
                     lox::error(&ErrorToken::new(&equals, self.symbol_table), "Invalid assignment target.");
                 }
             }
+        } else if self.match_types(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::PlusPlus,
+            TokenType::MinusMinus,
+        ]) {
+            let operator = self.previous();
+            // `b++` is sugar for `b += 1`, so it evaluates to the updated value.
+            let value_idx = if matches!(operator.token_type, TokenType::PlusPlus | TokenType::MinusMinus) {
+                let one = if self.untyped { Literal::Num(1.into()) } else { Literal::Int(1) };
+                self.expr_pool.add_expr(Expr::Literal { value: one })
+            } else {
+                self.assignment()?
+            };
+
+            if let Expr::Variable { name } = self.expr_pool.get_expr(expr_idx) {
+                let assign_expr = Expr::Assign {
+                    name: name.clone(),
+                    operator: Some(operator),
+                    value: value_idx,
+                };
+                let idx = self.expr_pool.add_expr(assign_expr);
+                return Ok(idx);
+            }
+
+            lox::error(&ErrorToken::new(&operator, self.symbol_table), "Invalid compound assignment target.");
         }
 
         Ok(expr_idx)
@@ -420,7 +565,7 @@ impl<'a> Parser<'a> {
     }
 
     fn comparison(&mut self) -> Result<ExprIdx, ParseError> {
-        let mut expr_idx = self.term()?;
+        let mut expr_idx = self.bit_or()?;
 
         while self.match_types(&[
             TokenType::Greater,
@@ -428,6 +573,75 @@ impl<'a> Parser<'a> {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            let operator = self.previous();
+            let right_idx = self.bit_or()?;
+            let binary_expr = Expr::Binary {
+                left: expr_idx,
+                operator,
+                right: right_idx,
+            };
+            expr_idx = self.expr_pool.add_expr(binary_expr);
+        }
+
+        Ok(expr_idx)
+    }
+
+    // Bitwise operators bind tighter than comparisons, so `a & b == 0` is `(a & b) == 0`.
+    fn bit_or(&mut self) -> Result<ExprIdx, ParseError> {
+        let mut expr_idx = self.bit_xor()?;
+
+        while self.match_types(&[TokenType::Pipe]) {
+            let operator = self.previous();
+            let right_idx = self.bit_xor()?;
+            let binary_expr = Expr::Binary {
+                left: expr_idx,
+                operator,
+                right: right_idx,
+            };
+            expr_idx = self.expr_pool.add_expr(binary_expr);
+        }
+
+        Ok(expr_idx)
+    }
+
+    fn bit_xor(&mut self) -> Result<ExprIdx, ParseError> {
+        let mut expr_idx = self.bit_and()?;
+
+        while self.match_types(&[TokenType::Caret]) {
+            let operator = self.previous();
+            let right_idx = self.bit_and()?;
+            let binary_expr = Expr::Binary {
+                left: expr_idx,
+                operator,
+                right: right_idx,
+            };
+            expr_idx = self.expr_pool.add_expr(binary_expr);
+        }
+
+        Ok(expr_idx)
+    }
+
+    fn bit_and(&mut self) -> Result<ExprIdx, ParseError> {
+        let mut expr_idx = self.shift()?;
+
+        while self.match_types(&[TokenType::Ampersand]) {
+            let operator = self.previous();
+            let right_idx = self.shift()?;
+            let binary_expr = Expr::Binary {
+                left: expr_idx,
+                operator,
+                right: right_idx,
+            };
+            expr_idx = self.expr_pool.add_expr(binary_expr);
+        }
+
+        Ok(expr_idx)
+    }
+
+    fn shift(&mut self) -> Result<ExprIdx, ParseError> {
+        let mut expr_idx = self.term()?;
+
+        while self.match_types(&[TokenType::LessLess, TokenType::GreaterGreater]) {
             let operator = self.previous();
             let right_idx = self.term()?;
             let binary_expr = Expr::Binary {
@@ -461,7 +675,7 @@ impl<'a> Parser<'a> {
     fn factor(&mut self) -> Result<ExprIdx, ParseError> {
         let mut expr_idx = self.unary()?;
 
-        while self.match_types(&[TokenType::Slash, TokenType::Star]) {
+        while self.match_types(&[TokenType::Slash, TokenType::Star, TokenType::Percent]) {
             let operator = self.previous();
             let right_idx = self.unary()?;
             let binary_expr = Expr::Binary {
@@ -562,6 +776,21 @@ impl<'a> Parser<'a> {
             return Ok(idx);
         }
 
+        // Type keywords in expression position are explicit conversions, e.g. `num(b)`.
+        // `num` shares its token type with number literals, but keywords carry no literal.
+        if self.check(&TokenType::Int) || (self.check(&TokenType::Number) && self.peek().literal == Literal::Void) {
+            let target = self.advance();
+            self.consume(TokenType::LeftParen, "Expect '(' after conversion type.")?;
+            let expr_idx = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after conversion.")?;
+            let cast_expr = Expr::Cast {
+                target,
+                expression: expr_idx,
+            };
+            let idx = self.expr_pool.add_expr(cast_expr);
+            return Ok(idx);
+        }
+
         if self.match_types(&[TokenType::Number, TokenType::String]) {
             let literal_expr = Expr::Literal {
                 value: self.previous().literal,
@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::analysis::WarningFilter;
+use crate::assembler;
+use crate::expr::ExprPool;
+use crate::il::Il;
+use crate::interpreter::Interpreter;
+use crate::lexer::scanner::Scanner;
+use crate::lexer::token::{Literal, Token, TokenType};
+use crate::lox::{self, CompileOptions, LoxError};
+use crate::lox_value::LoxValue;
+use crate::module::Module;
+use crate::parser::Parser;
+use crate::scope::{Scope, ScopeRef};
+use crate::stmt::Stmt;
+use crate::symbol::{Symbol, SymbolTable};
+use crate::vm::Vm;
+
+// Compiles the input line by line. Functions defined on a line stay available to the following
+// ones, any other line is compiled into a function of its own and run on the VM.
+pub struct Repl {
+    options: CompileOptions,
+    symbol_table: SymbolTable,
+    // Every line defining functions pushes a scope on top, so a line that fails to compile
+    // leaves no signatures without code behind.
+    scope: ScopeRef,
+    // IL of the functions defined so far, linked into every line that runs.
+    definitions: Il,
+    labels: usize,
+    lines: usize,
+}
+
+impl Repl {
+    pub fn new(options: &CompileOptions) -> Self {
+        Repl {
+            options: options.for_vm(),
+            symbol_table: SymbolTable::new(),
+            scope: Rc::new(RefCell::new(Scope::new(None, None, None))),
+            definitions: Il::new(),
+            labels: 0,
+            lines: 0,
+        }
+    }
+
+    // Runs a line and prints the value of a lone expression.
+    pub fn eval(&mut self, line: &str, out: &mut impl Write) -> Result<(), LoxError> {
+        lox::reset_errors();
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        // A pragma has a line of its own, so it holds for the rest of the session.
+        if line.starts_with("//!") {
+            return self.options.warnings.apply_pragmas(line);
+        }
+        // A lone expression doesn't need its semicolon.
+        let source = if line.ends_with(';') || line.ends_with('}') { line.to_string() } else { format!("{};", line) };
+        let tokens = {
+            let mut lexer = Scanner::new(&source, &mut self.symbol_table);
+            lexer.scan_tokens();
+
+            lexer.tokens
+        };
+        let (statements, mut expr_pool) = Parser::new(&self.symbol_table, tokens)
+            .parse()
+            .map_err(|_| LoxError::Error("Error during parsing".into()))?;
+        lox::check_errors()?;
+
+        // Lines are compiled into functions of their own, their variables would be gone by the next one.
+        if let Some(Stmt::Var { name, .. }) = statements.iter().find(|stmt| matches!(stmt, Stmt::Var { .. })) {
+            return Err(LoxError::CompilationError(format!(
+                "[line {}] Variables don't outlive their line in the REPL, declare '{}' inside a function.",
+                name.line,
+                self.symbol_table.resolve(name.lexeme)
+            )));
+        }
+        let defines = statements.iter().all(|stmt| matches!(stmt, Stmt::Function { .. }));
+        let (statements, entry, value) = if defines {
+            for stmt in &statements {
+                if let Stmt::Function { name, .. } = stmt {
+                    if self.scope.borrow().has_signature(name.lexeme) {
+                        return Err(LoxError::CompilationError(format!(
+                            "[line {}] Function '{}' is already defined.",
+                            name.line,
+                            self.symbol_table.resolve(name.lexeme)
+                        )));
+                    }
+                }
+            }
+            (statements, None, LoxValue::Void)
+        } else {
+            let (function, name, value) = self.wrap(statements, &expr_pool)?;
+            (vec![function], Some(name), value)
+        };
+
+        // The functions of a line are meant for the following ones, so it's compiled like an
+        // imported module, which doesn't have to call them itself.
+        let modules = [Module { name: Some("repl".into()), path: PathBuf::new(), statements, allowed: WarningFilter::default() }];
+        let (il, scope) = lox::gen_modules(
+            &modules,
+            &mut expr_pool,
+            &mut self.symbol_table,
+            &self.options,
+            Some(self.scope.clone()),
+            &mut self.labels,
+        )?;
+
+        let Some(entry) = entry else {
+            self.definitions.lines.extend(il.lines);
+            self.definitions.frames.extend(il.frames);
+            self.scope = scope;
+            return Ok(());
+        };
+        let mut program = self.definitions.clone();
+        program.lines.extend(il.lines);
+        program.frames.extend(il.frames);
+        let mut bytecode = Vec::new();
+        let debug_map = assembler::assemble_entry(
+            &program,
+            self.symbol_table.resolve(entry),
+            self.options.stack_size,
+            self.options.heap_size,
+            &mut bytecode,
+        )?;
+        let mut vm = Vm::new(&bytecode)?.with_debug_map(&debug_map);
+        vm.run(out)?;
+        if let Some(text) = returned(&vm, &value) {
+            writeln!(out, "{}", text)?;
+        }
+        Ok(())
+    }
+
+    // Wraps a line into `fn __repl_<n>__()`. A lone expression is returned from it, if it has a value.
+    fn wrap(&mut self, statements: Vec<Stmt>, expr_pool: &ExprPool) -> Result<(Stmt, Symbol, LoxValue), LoxError> {
+        self.lines += 1;
+        let name = self.symbol_table.intern(&format!("__repl_{}__", self.lines));
+        let value = match statements.as_slice() {
+            [Stmt::Expression { expression }] => {
+                let scope = Rc::new(RefCell::new(Scope::new(Some(self.scope.clone()), None, Some(name))));
+                let value = Interpreter::new(expr_pool, &mut self.symbol_table).expression_type(*expression, scope)?;
+                match value {
+                    LoxValue::Integer(_) | LoxValue::Number(_) | LoxValue::Boolean(_) | LoxValue::String(_) => Some((*expression, value)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let (return_type, body) = match value {
+            Some((expression, value)) => {
+                let keyword = Token::new(TokenType::Return, self.symbol_table.intern("return"), Literal::Void, 1, 1);
+                (value, vec![Stmt::Return { keyword, value: Some(expression) }])
+            }
+            None => (LoxValue::Void, statements),
+        };
+        let function = Stmt::Function {
+            name: Token::new(TokenType::Identifier, name, Literal::Void, 1, 1),
+            params: Vec::new(),
+            return_type: return_type.clone(),
+            body,
+        };
+        Ok((function, name, return_type))
+    }
+}
+
+// The value the line returned, read from the bottom of the stack. Strings point into the heap.
+fn returned(vm: &Vm, value: &LoxValue) -> Option<String> {
+    let word = |address: u32| vm.memory(address, 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+    match value {
+        LoxValue::Integer(_) => word(0).map(|value| (value as i32).to_string()),
+        LoxValue::Number(_) => word(0).map(|value| f32::from_bits(value).to_string()),
+        LoxValue::Boolean(_) => vm.memory(0, 1).map(|bytes| (bytes[0] != 0).to_string()),
+        LoxValue::String(_) => {
+            let address = word(0)?;
+            let text = vm.memory(address + 4, word(address)?)?;
+            Some(String::from_utf8_lossy(text).into_owned())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(lines: &[&str]) -> Vec<Result<String, String>> {
+        let mut repl = Repl::new(&CompileOptions::default());
+        lines
+            .iter()
+            .map(|line| {
+                let mut out = Vec::new();
+                repl.eval(line, &mut out)
+                    .map(|_| String::from_utf8(out).unwrap())
+                    .map_err(|err| err.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_definitions_persist() {
+        let results = session(&[
+            "fn square(x: int) -> int { return x * x; }",
+            "fn greet(name: str) -> str { print(\"hi\"); return name; }",
+            "square(7) + 1",
+            "greet(\"lox\")",
+            "square(2) == 4;",
+            "print(greet(\"you\"));",
+        ]);
+        let results: Vec<String> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, ["", "", "50\n", "hi\nlox\n", "true\n", "hi\nyou\n"]);
+    }
+
+    #[test]
+    fn test_variables_and_pragmas() {
+        let results = session(&[
+            "let y = 3;",
+            "y + 1",
+            "if (true) { let y = 3; print(\"y\"); }",
+            "//! allow(unused-variable, unused-function)",
+            "//! allow(everything)",
+        ]);
+        let message = "[line 1] Variables don't outlive their line in the REPL, declare 'y' inside a function.";
+        assert!(results[0].as_ref().is_err_and(|err| err.ends_with(message)));
+        assert!(results[1].as_ref().is_err());
+        assert_eq!(results[2], Ok("y\n".to_string()));
+        assert_eq!(results[3], Ok(String::new()));
+        assert!(results[4].as_ref().is_err_and(|err| err.ends_with("[line 1] Unknown warning 'everything' in pragma.")));
+    }
+
+    #[test]
+    fn test_errors_dont_end_the_session() {
+        let results = session(&[
+            "fn f() -> int { return \"no\"; }",
+            "fn f() -> int { return 1; }",
+            "fn f() -> int { return 2; }",
+            "f() / 0",
+            "f() * 3",
+        ]);
+        assert!(results[0].is_err());
+        assert_eq!(results[1], Ok(String::new()));
+        assert!(results[2].as_ref().is_err_and(|err| err.contains("'f' is already defined")));
+        assert!(results[3].as_ref().is_err_and(|err| err.contains("Division by zero.")));
+        assert_eq!(results[4], Ok("3\n".to_string()));
+    }
+}
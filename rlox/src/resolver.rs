@@ -31,6 +31,8 @@ pub struct Resolver<'a> {
     locals: FxHashMap<ExprIdx, usize>,
     current_function: FunctionType,
     current_class: ClassType,
+    // Loops enclosing the current statement within the current function.
+    loop_depth: usize,
     expr_pool: &'a ExprPool,
     symbol_table: &'a mut SymbolTable,
 }
@@ -42,6 +44,7 @@ impl<'a> Resolver<'a> {
             locals: FxHashMap::default(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            loop_depth: 0,
             expr_pool,
             symbol_table
         }
@@ -75,12 +78,32 @@ impl<'a> Resolver<'a> {
                 let else_branch = else_branch.as_deref();
                 self.if_stmt(*condition, then_branch, else_branch);
             }
-            Stmt::While { condition, body } => self.while_stmt(*condition, body),
-            Stmt::Function { name, params, return_type, body } => self.function_stmt(name, params, body),
+            Stmt::While { condition, body, increment } => {
+                self.while_stmt(*condition, body);
+                if let Some(increment) = increment {
+                    self.resolve_expr(*increment);
+                }
+            }
+            Stmt::Match { subject, arms, default, .. } => {
+                self.resolve_expr(*subject);
+                for arm in arms {
+                    self.resolve_statement(&arm.body);
+                }
+                if let Some(default) = default {
+                    self.resolve_statement(default);
+                }
+            }
+            Stmt::Break { keyword } if self.loop_depth == 0 => {
+                lox::error(&ErrorToken::new(keyword, self.symbol_table), "Can't use 'break' outside of a loop.");
+            }
+            Stmt::Continue { keyword } if self.loop_depth == 0 => {
+                lox::error(&ErrorToken::new(keyword, self.symbol_table), "Can't use 'continue' outside of a loop.");
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Import { .. } => (),
+            Stmt::Function { name, params, body, .. } => self.function_stmt(name, params, body),
             Stmt::Return { keyword, value } => {
-                return;
-                /*let value_idx = value.as_ref().copied();
-                self.return_stmt(keyword, value_idx);*/
+                let value_idx = value.as_ref().copied();
+                self.return_stmt(keyword, value_idx);
             }
             Stmt::Class {
                 name,
@@ -104,8 +127,9 @@ impl<'a> Resolver<'a> {
             Expr::Grouping { expression } => self.grouping_expr(*expression),
             Expr::Literal { .. } => (),
             Expr::Unary { operator, right } => self.unary_expr(operator, *right),
+            Expr::Cast { expression, .. } => self.grouping_expr(*expression),
             Expr::Variable { name } => self.variable_expr(expr_idx, name),
-            Expr::Assign { name, value } => self.assign_expr(expr_idx, name, *value),
+            Expr::Assign { name, value, .. } => self.assign_expr(expr_idx, name, *value),
             Expr::Logical {
                 left,
                 operator,
@@ -261,7 +285,9 @@ impl<'a> Resolver<'a> {
 
     fn while_stmt(&mut self, condition_idx: ExprIdx, body: &'a Stmt) {
         self.resolve_expr(condition_idx);
+        self.loop_depth += 1;
         self.resolve_statement(body);
+        self.loop_depth -= 1;
     }
 
     fn function_stmt(&mut self, name: &'a Token, params: &'a [Token], body: &'a [Stmt]) {
@@ -277,7 +303,9 @@ impl<'a> Resolver<'a> {
         function_type: FunctionType,
     ) {
         let enclosing_function = self.current_function;
+        let enclosing_loop_depth = self.loop_depth;
         self.current_function = function_type;
+        self.loop_depth = 0;
 
         self.begin_scope();
         for param in params {
@@ -288,6 +316,7 @@ impl<'a> Resolver<'a> {
         self.end_scope();
 
         self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
     }
 
     fn return_stmt(&mut self, keyword: &Token, value_idx: Option<ExprIdx>) {
@@ -339,7 +368,6 @@ impl<'a> Resolver<'a> {
             scope.insert(self.symbol_table.intern("this"), true);
         }
 
-        return unreachable!("Classes are not supported.");
         for method in methods {
             if let Stmt::Function { name, params, return_type: _, body } = method {
                 let declaration = if name.lexeme == self.symbol_table.intern("init") {
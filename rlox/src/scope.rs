@@ -12,6 +12,7 @@ pub struct Scope {
     scope_variables: rustc_hash::FxHashMap<usize, (usize, usize, LoxValue)>,
     scope_signatures: rustc_hash::FxHashMap<usize, (usize, LoxValue, Rc<RefCell<Vec<LoxValue>>>)>,
     pos: usize,
+    base: usize,
     generation: usize,
     scope_name: Option<Symbol> 
 }
@@ -21,11 +22,17 @@ impl Scope {
         let genereation = if let Some(g) = gen { g }
                           else if let Some(ref p) = parent { p.borrow().gen()+1 }
                           else { 1 };
+        // Function scopes get a fresh frame, nested scopes continue where the enclosing one stopped.
+        let base = match (&parent, name) {
+            (Some(p), None) => p.borrow().pos(),
+            _ => 0
+        };
         Scope {
             parent: parent,
             scope_variables: FxHashMap::default(),
             scope_signatures: FxHashMap::default(),
-            pos: 0,
+            pos: base,
+            base,
             generation: genereation,
             scope_name: name
         }
@@ -35,12 +42,13 @@ impl Scope {
     pub fn id(&self) -> &Option<Symbol> { &self.scope_name }
     pub fn has_parent(&self) -> bool { matches!(self.parent, Some(_)) }
     pub fn pos(&self) -> usize { self.pos }
+    pub fn base(&self) -> usize { self.base }
+    pub fn local_size(&self) -> usize { self.pos - self.base }
 
-    pub fn is_void(&self) -> bool { 
-        if let Some(Symbol(sym)) = self.scope_name {
-            let &(_, returns, _) = &self.scope_signatures.get(&sym).unwrap();
-            returns.size() == 0
-        } else { true }
+    pub fn fn_id(&self) -> Option<Symbol> {
+        if self.scope_name.is_some() { self.scope_name }
+        else if let Some(parent) = &self.parent { parent.borrow().fn_id() }
+        else { None }
     }
 
     pub fn add_signature(&mut self, name: Symbol, signature: &(usize, LoxValue, Rc<RefCell<Vec<LoxValue>>>), symbol_table: &SymbolTable) -> Result<(), LoxError> {
@@ -53,13 +61,13 @@ impl Scope {
     }
 
     pub fn get_signature(&self, name: Symbol, symbol_table: &SymbolTable) -> Result<(usize, LoxValue, Rc<RefCell<Vec<LoxValue>>>), LoxError> {
-        if self.scope_signatures.contains_key(&name.0) {
-            Ok(self.scope_signatures.get(&name.0).unwrap().clone())
+        if let Some(signature) = self.scope_signatures.get(&name.0) {
+            Ok(signature.clone())
         } else if let Some(parent) = &self.parent {
             let p = parent.borrow();
             p.get_signature(name, symbol_table)
         } else {
-            Err(LoxError::CompilationError(format!("Given function '{}' already exists in this scope.", symbol_table.resolve(name))))
+            Err(LoxError::CompilationError(format!("Couldn't find function '{}' in the current scope.", symbol_table.resolve(name))))
         }
     }
 
@@ -1,7 +1,7 @@
 use std::rc::Rc;
 
 use crate::expr::ExprIdx;
-use crate::lexer::token::Token;
+use crate::lexer::token::{Literal, Token};
 use crate::lox_value::LoxValue;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -27,6 +27,20 @@ pub enum Stmt {
     While {
         condition: ExprIdx,
         body: Rc<Stmt>,
+        // Only set for desugared `for` loops, so `continue` still runs it.
+        increment: Option<ExprIdx>,
+    },
+    Match {
+        keyword: Token,
+        subject: ExprIdx,
+        arms: Vec<MatchArm>,
+        default: Option<Rc<Stmt>>,
+    },
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
     },
     Function {
         name: Token,
@@ -43,6 +57,17 @@ pub enum Stmt {
         superclass: Option<ExprIdx>,
         methods: Vec<Stmt>,
     },
+    // Resolved before codegen, see `module::link`.
+    Import {
+        keyword: Token,
+        path: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchArm {
+    pub patterns: Vec<Literal>,
+    pub body: Rc<Stmt>,
 }
 
 impl From<Stmt> for Option<ExprIdx> {
@@ -53,7 +78,7 @@ impl From<Stmt> for Option<ExprIdx> {
                 name: _,
                 initializer,
             } => initializer,
-            _ => panic!("Should not be reached!"),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,572 @@
+use std::io::Write;
+
+use crate::assembler::{DebugMap, Opcode, HEADER_SIZE, MAGIC, NO_SIZE, VERSION};
+use crate::il::{ArithOp, CmpMode, Memory, Reg, Size};
+use crate::lox::LoxError;
+
+// Deep enough for any sane recursion, shallow enough to fail before the host runs out of memory.
+const MAX_CALL_DEPTH: usize = 1 << 16;
+// Bit of `&flg` that turns `cal` into a system call.
+const FLAG_SYSCALL: u32 = 1;
+// System call printing the `[u32, char[]]` string passed on the stack.
+const SYSCALL_PRINT: u32 = 0;
+
+struct CallFrame {
+    return_address: usize,
+    bp: u32,
+}
+
+// Runs the bytecode of the built-in assembler in-process. Memory is a single address space, the
+// stack comes first and the heap follows it, so heap strings are read with the same instructions
+// as locals.
+pub struct Vm<'a> {
+    code: &'a [u8],
+    entry: usize,
+    memory: Vec<u8>,
+    stack_size: u32,
+    // Free heap blocks as (address, length), sorted by address.
+    free: Vec<(u32, u32)>,
+    registers: [u32; Reg::ALL.len()],
+    calls: Vec<CallFrame>,
+    pc: usize,
+    // Start of the instruction being executed, errors are reported there.
+    current: usize,
+    debug_map: Option<&'a DebugMap>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a [u8]) -> Result<Self, LoxError> {
+        let malformed = |message: &str| LoxError::Error(format!("Malformed bytecode: {}", message));
+        if program.len() < HEADER_SIZE || &program[..3] != MAGIC {
+            return Err(malformed("missing header."));
+        }
+        if program[3] != VERSION {
+            return Err(malformed(&format!("unsupported version {}.", program[3])));
+        }
+        let field = |idx: usize| u32::from_le_bytes(program[4 + idx * 4..8 + idx * 4].try_into().unwrap());
+        let (entry, stack_size, heap_size, code_len) = (field(0), field(1), field(2), field(3) as usize);
+        let code = program.get(HEADER_SIZE..HEADER_SIZE + code_len).ok_or_else(|| malformed("truncated code."))?;
+
+        Ok(Vm {
+            code,
+            entry: entry as usize,
+            memory: vec![0; (stack_size + heap_size) as usize],
+            stack_size,
+            free: vec![(stack_size, heap_size)],
+            registers: [0; Reg::ALL.len()],
+            calls: Vec::new(),
+            pc: 0,
+            current: 0,
+            debug_map: None,
+        })
+    }
+
+    pub fn with_debug_map(mut self, debug_map: &'a DebugMap) -> Self {
+        self.debug_map = Some(debug_map);
+        self
+    }
+
+    // Executes from the entry point until control reaches the end of the code.
+    pub fn run(&mut self, out: &mut impl Write) -> Result<(), LoxError> {
+        self.pc = self.entry;
+        while self.pc < self.code.len() {
+            self.current = self.pc;
+            if let Err(message) = self.step(out) {
+                let location = self.debug_map.and_then(|map| map.lookup(self.current as u32));
+                return Err(LoxError::RuntimeError(match location {
+                    Some(location) => format!("[line {}] {}", location.line, message),
+                    None => message,
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    // Memory as `run` left it. The value `main` returned sits at the bottom of the stack.
+    pub fn memory(&self, address: u32, len: u32) -> Option<&[u8]> {
+        self.read(address, len).ok()
+    }
+
+    fn step(&mut self, out: &mut impl Write) -> Result<(), String> {
+        let byte = self.fetch()?;
+        let opcode = Opcode::ALL
+            .into_iter()
+            .find(|opcode| *opcode as u8 == byte)
+            .ok_or_else(|| format!("Invalid opcode {:#04x}.", byte))?;
+        match opcode {
+            Opcode::Stc => {
+                let size = self.fetch_size()?;
+                let value = self.fetch_sized(size)?;
+                self.push_value(size, value)?;
+            }
+            Opcode::Arith => {
+                let (op, size) = (self.fetch_arith_op()?, self.fetch_size()?);
+                let right = self.pop_value(size)?;
+                let left = self.pop_value(size)?;
+                self.push_value(size, arith(op, size, left, right)?)?;
+            }
+            Opcode::ArithReg => {
+                let op = self.fetch_arith_op()?;
+                // Flag registers have no suffix, their bits are combined like integers.
+                let size = match self.fetch()? {
+                    NO_SIZE => Size::Int,
+                    byte => decode(byte, &Size::ALL, "size")?,
+                };
+                let (a, b) = (self.fetch_reg()?, self.fetch_reg()?);
+                let value = arith(op, size, self.reg(a), self.reg(b))?;
+                self.set_reg(b, value);
+            }
+            Opcode::Cmp => {
+                let (size, mode) = (self.fetch_size()?, self.fetch_cmp_mode()?);
+                let width = size.bytes() as u32;
+                let sp = self.reg(Reg::Sp);
+                let right = self.read_value(sp.checked_sub(width).ok_or("Stack underflow.")?, size)?;
+                let left = self.read_value(sp.checked_sub(2 * width).ok_or("Stack underflow.")?, size)?;
+                self.set_reg(Reg::Bl, compare(mode, size, left, right) as u32);
+            }
+            Opcode::Cnv => {
+                let (from, to) = (self.fetch_size()?, self.fetch_size()?);
+                let value = self.pop_value(from)?;
+                let value = match from {
+                    Size::Byte => value as f64,
+                    Size::Int => value as i32 as f64,
+                    Size::Float => f32::from_bits(value) as f64,
+                };
+                let value = match to {
+                    Size::Byte => value as u8 as u32,
+                    Size::Int => value as i32 as u32,
+                    Size::Float => (value as f32).to_bits(),
+                };
+                self.push_value(to, value)?;
+            }
+            Opcode::Inc | Opcode::Dcr => {
+                let (size, reg) = (self.fetch_size()?, self.fetch_reg()?);
+                let amount = self.fetch_sized(size)?;
+                let value = if opcode == Opcode::Inc {
+                    self.reg(reg).wrapping_add(amount)
+                } else {
+                    self.reg(reg).wrapping_sub(amount)
+                };
+                self.set_reg(reg, value);
+            }
+            Opcode::MovTop => {
+                let reg = self.fetch_reg()?;
+                let size = if reg_width(reg) == 1 { Size::Byte } else { Size::Int };
+                let value = self.read_value(self.reg(Reg::Sp).checked_sub(size.bytes() as u32).ok_or("Stack underflow.")?, size)?;
+                self.set_reg(reg, value);
+            }
+            Opcode::MovReg => {
+                let (from, to) = (self.fetch_reg()?, self.fetch_reg()?);
+                self.set_reg(to, self.reg(from));
+            }
+            Opcode::MovImm => {
+                let reg = self.fetch_reg()?;
+                let value = self.fetch_u32()?;
+                self.set_reg(reg, value);
+            }
+            Opcode::Pop => {
+                let size = self.fetch_size()?;
+                self.pop_value(size)?;
+            }
+            Opcode::Rda => {
+                let size = self.fetch_size()?;
+                let value = self.read_value(self.reg(Reg::Ebx), size)?;
+                self.push_value(size, value)?;
+            }
+            Opcode::RdaReg => {
+                let reg = self.fetch_reg()?;
+                let size = if reg_width(reg) == 1 { Size::Byte } else { Size::Int };
+                self.push_value(size, self.reg(reg))?;
+            }
+            Opcode::Ldc => {
+                let size = self.fetch_size()?;
+                let value = self.read_value(self.reg(Reg::Sp).checked_sub(size.bytes() as u32).ok_or("Stack underflow.")?, size)?;
+                self.write(self.reg(Reg::Ebx), &value.to_le_bytes()[..size.bytes()])?;
+            }
+            Opcode::Mcp => {
+                let (from, to) = (self.fetch()?, self.fetch()?);
+                let (from, to) = (decode(from, &Memory::ALL, "memory")?, decode(to, &Memory::ALL, "memory")?);
+                let (source, dest, len) = (self.reg(Reg::Eax), self.reg(Reg::Ebx), self.reg(Reg::Ecx));
+                self.check_region(from, source, len)?;
+                self.check_region(to, dest, len)?;
+                let bytes = self.read(source, len)?.to_vec();
+                self.write(dest, &bytes)?;
+            }
+            Opcode::Alc => {
+                let address = self.alloc(self.reg(Reg::Ecx))?;
+                self.set_reg(Reg::Ebx, address);
+            }
+            Opcode::Del => self.dealloc(self.reg(Reg::Ebx), self.reg(Reg::Ecx))?,
+            Opcode::Cnd => {
+                let target = self.fetch_u32()?;
+                if self.reg(Reg::Bl) != 0 {
+                    self.jump(target)?;
+                }
+            }
+            Opcode::Jmp => {
+                let target = self.fetch_u32()?;
+                self.jump(target)?;
+            }
+            Opcode::JmpReg => {
+                let reg = self.fetch_reg()?;
+                self.jump(self.reg(reg))?;
+            }
+            Opcode::Cal => {
+                let target = self.fetch_u32()?;
+                if self.reg(Reg::Flg) & FLAG_SYSCALL != 0 {
+                    return self.syscall(target, out);
+                }
+                if self.calls.len() >= MAX_CALL_DEPTH {
+                    return Err("Call stack overflow.".into());
+                }
+                // The arguments on top of the stack become the start of the callee's frame.
+                let bp = self.reg(Reg::Sp).checked_sub(self.reg(Reg::Bl)).ok_or("Stack underflow.")?;
+                self.calls.push(CallFrame { return_address: self.pc, bp: self.reg(Reg::Bp) });
+                self.set_reg(Reg::Bp, bp);
+                self.jump(target)?;
+            }
+            Opcode::Ret => {
+                let frame = self.calls.pop().ok_or("'ret' outside of a function.")?;
+                // The return value replaces the whole frame, arguments included.
+                let len = self.reg(Reg::Bl);
+                let value = self.read(self.reg(Reg::Sp).checked_sub(len).ok_or("Stack underflow.")?, len)?.to_vec();
+                let bp = self.reg(Reg::Bp);
+                self.write(bp, &value)?;
+                self.set_reg(Reg::Sp, bp + len);
+                self.set_reg(Reg::Bp, frame.bp);
+                self.pc = frame.return_address;
+            }
+            Opcode::Raw => {
+                let len = self.fetch_u32()?;
+                let text = self.code.get(self.pc..self.pc + len as usize).ok_or("Truncated instruction.")?;
+                self.pc += len as usize;
+                self.push(&len.to_le_bytes())?;
+                self.push(text)?;
+            }
+        }
+        if self.reg(Reg::Sp) > self.stack_size {
+            // `dcr` past the bottom wraps around.
+            return Err(if self.reg(Reg::Sp) > u32::MAX / 2 { "Stack underflow." } else { "Stack overflow." }.into());
+        }
+        Ok(())
+    }
+
+    fn syscall(&mut self, number: u32, out: &mut impl Write) -> Result<(), String> {
+        if number != SYSCALL_PRINT {
+            return Err(format!("Unknown system call {:#x}.", number));
+        }
+        let len = self.reg(Reg::Bl);
+        let start = self.reg(Reg::Sp).checked_sub(len).ok_or("Stack underflow.")?;
+        let args = self.read(start, len)?;
+        let text = args
+            .get(..4)
+            .map(|header| u32::from_le_bytes(header.try_into().unwrap()) as usize)
+            .and_then(|text_len| args.get(4..4 + text_len))
+            .ok_or("Malformed print arguments.")?;
+        writeln!(out, "{}", String::from_utf8_lossy(text)).map_err(|err| err.to_string())?;
+        self.set_reg(Reg::Sp, start);
+        Ok(())
+    }
+
+    fn reg(&self, reg: Reg) -> u32 {
+        self.registers[reg as usize]
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: u32) {
+        self.registers[reg as usize] = if reg_width(reg) == 1 { value & 0xff } else { value };
+    }
+
+    fn jump(&mut self, target: u32) -> Result<(), String> {
+        if target as usize > self.code.len() {
+            return Err(format!("Jump to invalid address {:#x}.", target));
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> Result<u8, String> {
+        let byte = *self.code.get(self.pc).ok_or("Truncated instruction.")?;
+        self.pc += 1;
+        Ok(byte)
+    }
+
+    fn fetch_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.code.get(self.pc..self.pc + 4).ok_or("Truncated instruction.")?;
+        self.pc += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn fetch_sized(&mut self, size: Size) -> Result<u32, String> {
+        match size {
+            Size::Byte => self.fetch().map(u32::from),
+            Size::Int | Size::Float => self.fetch_u32(),
+        }
+    }
+
+    fn fetch_size(&mut self) -> Result<Size, String> {
+        let byte = self.fetch()?;
+        decode(byte, &Size::ALL, "size")
+    }
+
+    fn fetch_reg(&mut self) -> Result<Reg, String> {
+        let byte = self.fetch()?;
+        decode(byte, &Reg::ALL, "register")
+    }
+
+    fn fetch_arith_op(&mut self) -> Result<ArithOp, String> {
+        let byte = self.fetch()?;
+        decode(byte, &ArithOp::ALL, "operator")
+    }
+
+    fn fetch_cmp_mode(&mut self) -> Result<CmpMode, String> {
+        let byte = self.fetch()?;
+        decode(byte, &CmpMode::ALL, "comparison")
+    }
+
+    fn read(&self, address: u32, len: u32) -> Result<&[u8], String> {
+        self.memory
+            .get(address as usize..address as usize + len as usize)
+            .ok_or_else(|| format!("Invalid memory access at {:#x}.", address))
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
+        self.memory
+            .get_mut(address as usize..address as usize + bytes.len())
+            .ok_or_else(|| format!("Invalid memory access at {:#x}.", address))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn read_value(&self, address: u32, size: Size) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        bytes[..size.bytes()].copy_from_slice(self.read(address, size.bytes() as u32)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let sp = self.reg(Reg::Sp);
+        if sp as usize + bytes.len() > self.stack_size as usize {
+            return Err("Stack overflow.".into());
+        }
+        self.write(sp, bytes)?;
+        self.set_reg(Reg::Sp, sp + bytes.len() as u32);
+        Ok(())
+    }
+
+    fn push_value(&mut self, size: Size, value: u32) -> Result<(), String> {
+        self.push(&value.to_le_bytes()[..size.bytes()])
+    }
+
+    fn pop_value(&mut self, size: Size) -> Result<u32, String> {
+        let sp = self.reg(Reg::Sp).checked_sub(size.bytes() as u32).ok_or("Stack underflow.")?;
+        let value = self.read_value(sp, size)?;
+        self.set_reg(Reg::Sp, sp);
+        Ok(value)
+    }
+
+    fn check_region(&self, memory: Memory, address: u32, len: u32) -> Result<(), String> {
+        let (start, end) = match memory {
+            Memory::Stack => (0, self.stack_size as usize),
+            Memory::Heap => (self.stack_size as usize, self.memory.len()),
+        };
+        if (address as usize) < start || address as usize + len as usize > end {
+            return Err(format!("Address {:#x} is outside of the {} region.", address, memory));
+        }
+        Ok(())
+    }
+
+    // First fit over the free list.
+    fn alloc(&mut self, len: u32) -> Result<u32, String> {
+        let len = len.max(1);
+        let idx = self.free.iter().position(|(_, free)| *free >= len).ok_or("Out of heap memory.")?;
+        let (address, free) = self.free[idx];
+        if free == len {
+            self.free.remove(idx);
+        } else {
+            self.free[idx] = (address + len, free - len);
+        }
+        Ok(address)
+    }
+
+    fn dealloc(&mut self, address: u32, len: u32) -> Result<(), String> {
+        let len = len.max(1);
+        self.check_region(Memory::Heap, address, len)?;
+        let idx = self.free.partition_point(|(start, _)| *start < address);
+        let overlaps_next = self.free.get(idx).is_some_and(|(start, _)| address + len > *start);
+        let overlaps_prev = idx > 0 && self.free[idx - 1].0 + self.free[idx - 1].1 > address;
+        if overlaps_next || overlaps_prev {
+            return Err(format!("Invalid 'del' of {:#x}.", address));
+        }
+        self.free.insert(idx, (address, len));
+        // Coalesce with the neighbours.
+        if self.free.get(idx + 1).is_some_and(|(start, _)| *start == address + len) {
+            self.free[idx].1 += self.free.remove(idx + 1).1;
+        }
+        if idx > 0 && self.free[idx - 1].0 + self.free[idx - 1].1 == address {
+            self.free[idx - 1].1 += self.free.remove(idx).1;
+        }
+        Ok(())
+    }
+}
+
+fn decode<T: Copy>(byte: u8, all: &[T], what: &str) -> Result<T, String> {
+    all.get(byte as usize).copied().ok_or_else(|| format!("Invalid {} operand {:#04x}.", what, byte))
+}
+
+fn reg_width(reg: Reg) -> usize {
+    match reg {
+        Reg::Bl | Reg::Dl | Reg::Flg => 1,
+        _ => 4,
+    }
+}
+
+fn arith(op: ArithOp, size: Size, left: u32, right: u32) -> Result<u32, String> {
+    if size == Size::Float {
+        let (left, right) = (f32::from_bits(left), f32::from_bits(right));
+        let value = match op {
+            ArithOp::Add => left + right,
+            ArithOp::Sub => left - right,
+            ArithOp::Mul => left * right,
+            ArithOp::Div => left / right,
+            ArithOp::Mod => left % right,
+            _ => return Err(format!("'{}' isn't defined for floats.", op)),
+        };
+        return Ok(value.to_bits());
+    }
+    let (left, right) = (left as i32, right as i32);
+    let value = match op {
+        ArithOp::Add => left.wrapping_add(right),
+        ArithOp::Sub => left.wrapping_sub(right),
+        ArithOp::Mul => left.wrapping_mul(right),
+        ArithOp::Div | ArithOp::Mod if right == 0 => return Err("Division by zero.".into()),
+        ArithOp::Div => left.wrapping_div(right),
+        ArithOp::Mod => left.wrapping_rem(right),
+        ArithOp::And => left & right,
+        ArithOp::Or => left | right,
+        ArithOp::Xor => left ^ right,
+        ArithOp::Shl => left.wrapping_shl(right as u32),
+        ArithOp::Shr => left.wrapping_shr(right as u32),
+    };
+    Ok(value as u32)
+}
+
+fn compare(mode: CmpMode, size: Size, left: u32, right: u32) -> bool {
+    let ordering = match size {
+        Size::Byte => left.cmp(&right),
+        Size::Int => (left as i32).cmp(&(right as i32)),
+        // NaN compares unequal to everything.
+        Size::Float => match f32::from_bits(left).partial_cmp(&f32::from_bits(right)) {
+            Some(ordering) => ordering,
+            None => return mode == CmpMode::Neq,
+        },
+    };
+    match mode {
+        CmpMode::Les => ordering.is_lt(),
+        CmpMode::Leq => ordering.is_le(),
+        CmpMode::Grt => ordering.is_gt(),
+        CmpMode::Geq => ordering.is_ge(),
+        CmpMode::Equ => ordering.is_eq(),
+        CmpMode::Neq => ordering.is_ne(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::{self, CompileOptions};
+
+    fn run(source: &str) -> Result<String, LoxError> {
+        let mut out = Vec::new();
+        lox::run_source(source, &CompileOptions::default(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_print_and_strings() {
+        let source = "fn main() -> void { let s = \"hello\"; print(s); s = \"bye\"; print(s); }";
+        assert_eq!(run(source).unwrap(), "hello\nbye\n");
+    }
+
+    #[test]
+    fn test_calls_and_control_flow() {
+        let source = "
+            fn fib(n: int) -> int { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }
+            fn main() -> void {
+                let i = 0;
+                while (true) {
+                    i++;
+                    if (i == 3) { continue; }
+                    if (fib(i) > 20) { break; }
+                    match (i % 5) { 0 => print(\"0\"); 1 => print(\"1\"); 2 => print(\"2\"); 3 => print(\"3\"); else => print(\"-\"); }
+                }
+                if (i == 8 && int(2.5 * 2.0) == 5) { print(\"done\"); }
+            }";
+        assert_eq!(run(source).unwrap(), "1\n2\n-\n0\n1\n2\ndone\n");
+    }
+
+    #[test]
+    fn test_int_operators() {
+        let source = "
+            fn check(ok: bool) -> void { if (ok) { print(\"ok\"); } else { print(\"FAILED\"); } }
+            fn main() -> void {
+                let a = 7;
+                let b = 3;
+                check(a / b == 2 && -a / b == -2 && a % b == 1);
+                check((a & b) == 3 && (a | 8) == 15 && (a ^ b) == 4);
+                check(1 << 4 == 16 && 256 >> b == 32);
+                let max = 2147483647;
+                check(max + 1 == -max - 1);
+            }";
+        assert_eq!(run(source).unwrap(), "ok\nok\nok\nok\n");
+    }
+
+    #[test]
+    fn test_int_literals_as_num() {
+        let source = "
+            fn half(x: num) -> num { return x / 2; }
+            fn main() -> void {
+                let b: num = 0;
+                while (b < 3) { b = b + 1; }
+                b += 2;
+                if (half(b) == 2.5 && 1 + half(-1) == 0.5) { print(\"ok\"); }
+            }";
+        assert_eq!(run(source).unwrap(), "ok\n");
+        let source = "fn main() -> void { let a = 1; let b: num = a; }";
+        assert!(run(source).unwrap_err().to_string().contains("declared as 'num' but initialized with 'int'"));
+    }
+
+    #[test]
+    fn test_compound_assignment_with_calls() {
+        let source = "
+            fn two() -> int { let x = 1; x += 1; return x; }
+            fn main() -> void {
+                let pad = 7;
+                let v = 10;
+                v += two() * 3;
+                v -= v / two();
+                let f = 1.5;
+                f *= num(two()) + 0.5;
+                if (v == 8 && f == 3.75 && pad == 7) { print(\"ok\"); }
+            }";
+        assert_eq!(run(source).unwrap(), "ok\n");
+
+        // The slot address is computed once, before the call.
+        let il = lox::compile_il("fn f() -> int { return 1; } fn main() -> void { let a = 0; let v = 1; v += f(); }", &CompileOptions::default()).unwrap();
+        let mut text = Vec::new();
+        il.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let compound = &text[text.find("compound assignment").unwrap()..];
+        assert_eq!(compound.matches("mov &bp &ebx").count(), 1, "{}", compound);
+    }
+
+    #[test]
+    fn test_runtime_errors_have_locations() {
+        let source = "fn main() -> void {\n let a = 0;\n let b = 1 / a;\n}";
+        match run(source) {
+            Err(LoxError::RuntimeError(message)) => assert_eq!(message, "[line 3] Division by zero."),
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+        let source = "fn f(n: int) -> int { return f(n + 1); } fn main() -> void { let a = f(0); }";
+        assert!(matches!(run(source), Err(LoxError::RuntimeError(_))));
+    }
+}